@@ -1,14 +1,12 @@
-__sven_load_secrets() {
-    if [ -z "$SECRETS_LOADED" ]; then
-        export SECRETS_LOADED=1
-        eval "$(sven export --shell bash)"
-    fi
+__sven_hook() {
+    eval "$(sven hook --shell bash)"
 }
 
 # Load secrets on shell start
-__sven_load_secrets
+__sven_hook
 
-# Add to PROMPT_COMMAND to check when directory changes
-if [[ $PROMPT_COMMAND != *"__sven_load_secrets"* ]]; then
-    PROMPT_COMMAND="__sven_load_secrets;$PROMPT_COMMAND"
+# Apply changed secrets before every prompt; cheap while the daemon is unlocked,
+# otherwise only re-reads secrets when the directory changes
+if [[ $PROMPT_COMMAND != *"__sven_hook"* ]]; then
+    PROMPT_COMMAND="__sven_hook;$PROMPT_COMMAND"
 fi
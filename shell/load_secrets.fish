@@ -1,12 +1,8 @@
-function load_secrets --on-variable PWD
-    if not set -q SECRETS_LOADED
-        set -g SECRETS_LOADED 1
-        eval (sven export --shell fish)
-    end
+# Apply changed secrets before every prompt; cheap while the daemon is unlocked,
+# otherwise only re-reads secrets when the directory changes
+function __sven_hook --on-event fish_prompt
+    sven hook --shell fish | source
 end
 
 # Initial load when shell starts
-if not set -q SECRETS_LOADED
-    set -g SECRETS_LOADED 1
-    eval (sven export --shell fish)
-end
+__sven_hook
@@ -0,0 +1,20 @@
+function global:__sven_hook {
+    # Invoke-Expression rejects an empty string, which is what an unchanged
+    # environment produces
+    $out = sven hook --shell pwsh | Out-String
+    if ($out.Trim()) {
+        Invoke-Expression $out
+    }
+}
+
+# Load secrets on shell start
+__sven_hook
+
+# Apply changed secrets before every prompt, keeping the existing prompt
+if (-not (Test-Path Function:\__sven_prompt)) {
+    Copy-Item Function:\prompt Function:\global:__sven_prompt
+    function global:prompt {
+        __sven_hook
+        __sven_prompt
+    }
+}
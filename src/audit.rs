@@ -0,0 +1,175 @@
+use crate::backup;
+use crate::daemon;
+use crate::db;
+use crate::error::{Result, SvenError};
+use crate::logging;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+// What was done to a secret. Values are never recorded.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditAction {
+    Read,
+    Add,
+    Remove,
+    Rollback,
+    Restore,
+    // Written to a backup bundle
+    Backup,
+    // Decrypted and encrypted again for a new key or recipients list
+    Reencrypt,
+}
+
+impl AuditAction {
+    fn as_str(self) -> &'static str {
+        match self {
+            AuditAction::Read => "read",
+            AuditAction::Add => "add",
+            AuditAction::Remove => "remove",
+            AuditAction::Rollback => "rollback",
+            AuditAction::Restore => "restore",
+            AuditAction::Backup => "backup",
+            AuditAction::Reencrypt => "reencrypt",
+        }
+    }
+}
+
+// The process that read or changed a secret
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Actor {
+    pub uid: u32,
+    pub pid: Option<i32>,
+    pub exe: Option<String>,
+}
+
+impl Actor {
+    // This sven process, for commands that run without the daemon
+    pub fn current() -> Self {
+        Self {
+            uid: daemon::current_uid(),
+            pid: Some(std::process::id() as i32),
+            exe: std::env::current_exe().ok().map(|exe| exe.display().to_string()),
+        }
+    }
+
+    // A daemon client, identified by its socket credentials
+    pub fn peer(uid: u32, pid: Option<i32>) -> Self {
+        Self {
+            uid,
+            pid,
+            exe: pid
+                .and_then(|pid| std::fs::read_link(format!("/proc/{}/exe", pid)).ok())
+                .map(|exe| exe.display().to_string()),
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        format!(
+            "uid={} pid={} exe={}",
+            self.uid,
+            self.pid.map(|pid| pid.to_string()).unwrap_or_else(|| "unknown".into()),
+            self.exe.as_deref().unwrap_or("unknown")
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AuditEntry {
+    pub time: i64,
+    pub action: AuditAction,
+    pub profile: String,
+    pub key: String,
+    // Unset for reads made without the daemon, which don't resolve the scope
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    #[serde(flatten)]
+    pub actor: Actor,
+}
+
+impl AuditEntry {
+    pub fn line(&self) -> String {
+        let label = match &self.scope {
+            Some(scope) => backup::entry_label(&self.profile, &self.key, scope),
+            None => format!("{}/{}", self.profile, self.key),
+        };
+        format!(
+            "{}  {:<9}  {}  {}",
+            logging::format_timestamp(self.time),
+            self.action.as_str(),
+            label,
+            self.actor.describe()
+        )
+    }
+}
+
+// Append-only record of who read or changed which secret, one JSON object per line
+pub struct AuditLog {
+    file: Mutex<File>,
+}
+
+impl AuditLog {
+    fn path() -> Result<PathBuf> {
+        Ok(logging::state_dir()?.join("audit.log"))
+    }
+
+    pub fn open() -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .mode(0o600)
+            .open(Self::path()?)?;
+        Ok(Self { file: Mutex::new(file) })
+    }
+
+    pub fn record(
+        &self,
+        actor: &Actor,
+        action: AuditAction,
+        profile: &str,
+        key: &str,
+        scope: Option<&str>,
+    ) -> Result<()> {
+        let entry = AuditEntry {
+            time: db::unix_now(),
+            action,
+            profile: profile.to_string(),
+            key: key.to_string(),
+            scope: scope.map(str::to_string),
+            actor: actor.clone(),
+        };
+        // One write per line, so concurrent writers never interleave entries
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+        self.file.lock().unwrap().write_all(line.as_bytes())?;
+        Ok(())
+    }
+
+    // Entries for `key` (any if None) recorded at or after `since`, oldest first
+    pub fn read(key: Option<&str>, since: Option<i64>) -> Result<Vec<AuditEntry>> {
+        let file = match File::open(Self::path()?) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut entries = Vec::new();
+        for (number, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry: AuditEntry = serde_json::from_str(&line).map_err(|e| {
+                SvenError::ConfigError(format!("Invalid audit log entry on line {}: {}", number + 1, e))
+            })?;
+            if key.is_some_and(|key| entry.key != key) || since.is_some_and(|since| entry.time < since) {
+                continue;
+            }
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+}
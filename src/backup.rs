@@ -0,0 +1,122 @@
+use crate::db::GLOBAL_SCOPE;
+use crate::error::{Result, SvenError};
+use crate::secret::SecretString;
+use serde::{Deserialize, Serialize};
+
+// Bumped whenever the bundle layout changes incompatibly; version 2 stores
+// values as bytes instead of strings
+pub const BUNDLE_VERSION: u32 = 2;
+
+// Oldest bundle version that can still be restored
+const MIN_BUNDLE_VERSION: u32 = 1;
+
+// Config rows tied to the machine's own keyring, never restored
+pub const MACHINE_CONFIG_KEYS: &[&str] = &["gpg_key", "recipients"];
+
+// Plaintext contents of a backup; only ever written to disk GPG-encrypted
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Bundle {
+    pub version: u32,
+    pub created_at: u64,
+    pub profiles: Vec<String>,
+    pub config: Vec<(String, String)>,
+    pub variables: Vec<BundleEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BundleEntry {
+    pub profile: String,
+    pub key: String,
+    pub scope: String,
+    pub value: SecretString,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub created_at: Option<i64>,
+    #[serde(default)]
+    pub updated_at: Option<i64>,
+}
+
+impl Bundle {
+    pub fn to_json(&self) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec(self)?)
+    }
+
+    pub fn from_json(data: &[u8]) -> Result<Self> {
+        // Check the version before the layout so newer bundles get a clear
+        // error. Only the version is read; the values are skipped, and later
+        // deserialized straight into SecretStrings.
+        let header: BundleHeader = serde_json::from_slice(data)?;
+        if !(MIN_BUNDLE_VERSION as u64..=BUNDLE_VERSION as u64).contains(&header.version) {
+            return Err(SvenError::ConfigError(format!(
+                "Unsupported backup version {} (expected {} to {})",
+                header.version, MIN_BUNDLE_VERSION, BUNDLE_VERSION
+            )));
+        }
+        Ok(serde_json::from_slice(data)?)
+    }
+}
+
+#[derive(Deserialize)]
+struct BundleHeader {
+    #[serde(default)]
+    version: u64,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RestoreMode {
+    /// Add new secrets and update changed ones, keep everything else
+    Merge,
+    /// Make the store match the backup exactly, removing secrets not in it
+    Overwrite,
+    /// Only add secrets that don't exist yet
+    SkipExisting,
+}
+
+// A secret's (profile, key, scope)
+pub type SecretId = (String, String, String);
+
+// What a restore did to each secret, per outcome
+#[derive(Default, Debug)]
+pub struct RestoreSummary {
+    pub added: Vec<SecretId>,
+    pub updated: Vec<SecretId>,
+    pub unchanged: Vec<SecretId>,
+    pub skipped: Vec<SecretId>,
+    pub removed: Vec<SecretId>,
+}
+
+impl RestoreSummary {
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        for (marker, ids) in [
+            ("+", &self.added),
+            ("~", &self.updated),
+            ("-", &self.removed),
+            ("!", &self.skipped),
+        ] {
+            for (profile, key, scope) in ids {
+                lines.push(format!("  {} {}", marker, entry_label(profile, key, scope)));
+            }
+        }
+        lines.push(format!(
+            "{} added, {} updated, {} removed, {} skipped, {} unchanged",
+            self.added.len(),
+            self.updated.len(),
+            self.removed.len(),
+            self.skipped.len(),
+            self.unchanged.len()
+        ));
+        lines
+    }
+}
+
+pub fn entry_label(profile: &str, key: &str, scope: &str) -> String {
+    if scope == GLOBAL_SCOPE {
+        format!("{}/{}", profile, key)
+    } else {
+        format!("{}/{} (scope: {})", profile, key, scope)
+    }
+}
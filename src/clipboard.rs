@@ -0,0 +1,103 @@
+use crate::error::{Result, SvenError};
+use crate::secret;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use zeroize::Zeroizing;
+
+// Environment variable through which `copy_with_clear` hands the clearing
+// process a digest of the copied value, kept out of its command line
+pub const DIGEST_VAR: &str = "SVEN_CLIPBOARD_DIGEST";
+
+// A program and its arguments
+type Tool = (&'static str, &'static [&'static str]);
+
+// Picks the clipboard tools for the current session, as (copy, paste)
+fn clipboard_commands() -> Result<(Tool, Tool)> {
+    if cfg!(target_os = "macos") {
+        return Ok((("pbcopy", &[]), ("pbpaste", &[])));
+    }
+    if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        return Ok((("wl-copy", &[]), ("wl-paste", &["--no-newline"])));
+    }
+    if std::env::var_os("DISPLAY").is_some() {
+        return Ok((
+            ("xclip", &["-selection", "clipboard"]),
+            ("xclip", &["-selection", "clipboard", "-o"]),
+        ));
+    }
+    Err(SvenError::ConfigError(
+        "No clipboard available (need pbcopy, wl-copy or xclip)".into(),
+    ))
+}
+
+fn copy(value: &[u8]) -> Result<()> {
+    let ((program, args), _) = clipboard_commands()?;
+
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| SvenError::ConfigError(format!("Failed to run {}: {}", program, e)))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(value)?;
+    }
+    if !child.wait()?.success() {
+        return Err(SvenError::ConfigError(format!("{} failed", program)));
+    }
+    Ok(())
+}
+
+// Copies `value` to the clipboard and schedules it to be cleared after
+// `clear_after` seconds by a detached `sven clear-clipboard` that outlives
+// this process
+pub fn copy_with_clear(value: &str, clear_after: u64) -> Result<()> {
+    use std::os::unix::process::CommandExt;
+
+    copy(value.as_bytes())?;
+
+    if clear_after > 0 {
+        let mut clear = Command::new(std::env::current_exe()?);
+        clear
+            .args(["clear-clipboard", "--after", &clear_after.to_string()])
+            .env(DIGEST_VAR, format!("{:x}", secret::digest(value.as_bytes())))
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        // A session of its own, so closing the terminal doesn't SIGHUP it
+        // before it has cleared the clipboard
+        // SAFETY: setsid is async-signal-safe and touches no parent state
+        unsafe {
+            clear.pre_exec(|| {
+                if libc::setsid() == -1 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        clear.spawn()?;
+    }
+
+    Ok(())
+}
+
+// Waits `after` seconds, then clears the clipboard unless something else has
+// been copied since, so only the secret itself is wiped
+pub fn clear_after(digest: u64, after: u64) -> Result<()> {
+    std::thread::sleep(std::time::Duration::from_secs(after));
+
+    let (_, (program, args)) = clipboard_commands()?;
+    let output = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .map_err(|e| SvenError::ConfigError(format!("Failed to run {}: {}", program, e)))?;
+    let contents = Zeroizing::new(output.stdout);
+    // An empty or unreadable clipboard has nothing left to clear
+    if output.status.success() && secret::digest(&contents) == digest {
+        copy(b"")?;
+    }
+    Ok(())
+}
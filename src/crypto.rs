@@ -1,14 +1,20 @@
 use crate::error::{Result, SvenError};
+use crate::secret;
 use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use dialoguer::Select;
-use gpgme::{Context, Protocol, Validity};
+use gpgme::{Context, Data, EncryptFlags, Key, Protocol, Validity};
 use rusqlite::params;
+use std::io::Write;
+use zeroize::Zeroizing;
 
 const GPG_KEY_CONFIG: &str = "gpg_key";
+const RECIPIENTS_CONFIG: &str = "recipients";
 
 pub struct CryptoManager {
     ctx: Context,
     key_id: Option<String>,
+    // Every value is encrypted to all of these keys; defaults to just `key_id`
+    recipients: Vec<String>,
 }
 
 impl CryptoManager {
@@ -19,6 +25,7 @@ impl CryptoManager {
         Ok(Self { 
             ctx,
             key_id: None,
+            recipients: Vec::new(),
         })
     }
 
@@ -44,23 +51,7 @@ impl CryptoManager {
             return Ok(keys[0].id().unwrap_or_default().to_string());
         }
 
-        let key_strings: Vec<String> = keys
-            .iter()
-            .map(|key| {
-                format!(
-                    "{} ({}) <{}>",
-                    key.id().unwrap_or("Unknown"),
-                    key.user_ids()
-                        .next()
-                        .and_then(|uid| uid.name().ok().and_then(|s| Some(s)))
-                        .unwrap_or("Unknown"),
-                    key.user_ids()
-                        .next()
-                        .and_then(|uid| uid.email().ok().and_then(|s| Some(s)))
-                        .unwrap_or("Unknown")
-                )
-            })
-            .collect();
+        let key_strings: Vec<String> = keys.iter().map(Self::format_key).collect();
 
         let selection = Select::new()
             .with_prompt("Select GPG key for encryption")
@@ -88,26 +79,178 @@ impl CryptoManager {
         };
 
         self.key_id = Some(key_id);
+        self.load_recipients(db)
+    }
+
+    pub fn key_id(&self) -> Option<&str> {
+        self.key_id.as_deref()
+    }
+
+    // Prompts for a new key among the usable secret keys
+    pub fn choose_key(&mut self) -> Result<String> {
+        Self::select_key(&mut self.ctx)
+    }
+
+    // Swaps in a new own key and returns the previous one
+    pub fn set_key_id(&mut self, key_id: Option<String>) -> Option<String> {
+        std::mem::replace(&mut self.key_id, key_id)
+    }
+
+    pub fn save_key_id(&self, db: &rusqlite::Connection) -> Result<()> {
+        let key_id = self.key_id.as_ref().ok_or(SvenError::NoKeySelected)?;
+        db.execute(
+            "INSERT OR REPLACE INTO config (key, value) VALUES (?1, ?2)",
+            params![GPG_KEY_CONFIG, key_id],
+        )?;
+        Ok(())
+    }
+
+    // Reads the recipients list from config, falling back to the selected key
+    pub fn load_recipients(&mut self, db: &rusqlite::Connection) -> Result<()> {
+        let mut stmt = db.prepare("SELECT value FROM config WHERE key = ?1")?;
+        let mut rows = stmt.query(params![RECIPIENTS_CONFIG])?;
+
+        self.recipients = if let Some(row) = rows.next()? {
+            let value: String = row.get(0)?;
+            value.split(',').filter(|id| !id.is_empty()).map(str::to_string).collect()
+        } else {
+            self.key_id.iter().cloned().collect()
+        };
+        Ok(())
+    }
+
+    pub fn save_recipients(&self, db: &rusqlite::Connection) -> Result<()> {
+        db.execute(
+            "INSERT OR REPLACE INTO config (key, value) VALUES (?1, ?2)",
+            params![RECIPIENTS_CONFIG, self.recipients.join(",")],
+        )?;
         Ok(())
     }
 
+    pub fn recipients(&self) -> &[String] {
+        &self.recipients
+    }
+
+    // Swaps in a new recipients list and returns the previous one
+    pub fn set_recipients(&mut self, recipients: Vec<String>) -> Vec<String> {
+        std::mem::replace(&mut self.recipients, recipients)
+    }
+
+    // Looks up a public key usable for encryption and returns its key ID
+    pub fn find_recipient(&mut self, key_id: &str) -> Result<String> {
+        let key = self.ctx.get_key(key_id)?;
+        if key.is_expired() || key.is_revoked() || key.is_disabled() || key.is_invalid() || !key.can_encrypt() {
+            return Err(SvenError::ConfigError(format!("Key {} cannot be used for encryption", key_id)));
+        }
+        Ok(key.id().unwrap_or_default().to_string())
+    }
+
+    // Resolves a key ID or fingerprint to the key ID recipients are stored
+    // by, without checking that the key is still usable
+    pub fn lookup_key_id(&mut self, key_id: &str) -> Result<String> {
+        Ok(self.ctx.get_key(key_id)?.id().unwrap_or_default().to_string())
+    }
+
+    // Whether a secret key is available here, i.e. we can decrypt for it
+    pub fn has_secret_key(&mut self, key_id: &str) -> bool {
+        self.ctx.get_secret_key(key_id).is_ok()
+    }
+
+    // Formats a key as "ID (name) <email>" for display
+    pub fn describe_key(&mut self, key_id: &str) -> String {
+        match self.ctx.get_key(key_id) {
+            Ok(key) => Self::format_key(&key),
+            Err(_) => format!("{} (not in keyring)", key_id),
+        }
+    }
+
+    fn format_key(key: &Key) -> String {
+        format!(
+            "{} ({}) <{}>",
+            key.id().unwrap_or("Unknown"),
+            key.user_ids().next().and_then(|uid| uid.name().ok()).unwrap_or("Unknown"),
+            key.user_ids().next().and_then(|uid| uid.email().ok()).unwrap_or("Unknown")
+        )
+    }
+
     pub fn encrypt(&mut self, data: &[u8]) -> Result<String> {
-        let key_id = self.key_id.as_ref().ok_or(SvenError::NoKeySelected)?;
-        let key = self.ctx.get_secret_key(key_id)?;
-        if key.is_invalid() {
+        if self.recipients.is_empty() {
             return Err(SvenError::NoKeySelected);
         }
+
+        let mut keys = Vec::new();
+        for key_id in &self.recipients {
+            let key = self.ctx.get_key(key_id)?;
+            if key.is_invalid() {
+                return Err(SvenError::ConfigError(format!("Recipient key {} is invalid", key_id)));
+            }
+            keys.push(key);
+        }
         
+        // Recipients are added explicitly by key ID, so they are trusted even
+        // when they are not signed in the local web of trust
         let mut encrypted = Vec::new();
-        self.ctx.encrypt(Some(&key), data, &mut encrypted)?;
+        self.ctx.encrypt_with_flags(&keys, data, &mut encrypted, EncryptFlags::ALWAYS_TRUST)?;
         
         Ok(BASE64.encode(encrypted))
     }
 
-    pub fn decrypt(&mut self, data: &str) -> Result<Vec<u8>> {
+    // Encrypts a standalone ASCII-armored message, either to the recipients
+    // plus `extra` keys or, when `symmetric` is set, with a passphrase
+    pub fn encrypt_armored(&mut self, data: &[u8], extra: &[String], symmetric: bool) -> Result<Vec<u8>> {
+        // Keys are looked up before armor is switched on, so nothing between
+        // the two set_armor calls can return early and leave it on for encrypt()
+        let mut keys = Vec::new();
+        if !symmetric {
+            for key_id in self.recipients.iter().chain(extra) {
+                keys.push(self.ctx.get_key(key_id)?);
+            }
+        }
+
+        let mut encrypted = Vec::new();
+        self.ctx.set_armor(true);
+        let result = if symmetric {
+            self.ctx.encrypt_symmetric(data, &mut encrypted)
+        } else {
+            self.ctx
+                .encrypt_with_flags(&keys, data, &mut encrypted, EncryptFlags::ALWAYS_TRUST)
+                .map(|_| ())
+        };
+        self.ctx.set_armor(false);
+        result?;
+
+        Ok(encrypted)
+    }
+
+    pub fn decrypt_armored(&mut self, data: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+        self.decrypt_bytes(data)
+    }
+
+    pub fn decrypt(&mut self, data: &str) -> Result<Zeroizing<Vec<u8>>> {
         let encrypted = BASE64.decode(data).map_err(|e| SvenError::ConfigError(e.to_string()))?;
-        let mut decrypted = Vec::new();
-        self.ctx.decrypt(encrypted.as_slice(), &mut decrypted)?;
+        self.decrypt_bytes(&encrypted)
+    }
+
+    fn decrypt_bytes(&mut self, data: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+        // The plaintext is rarely longer than the ciphertext, so this usually
+        // never has to grow
+        let mut decrypted = Zeroizing::new(Vec::with_capacity(data.len()));
+        let output = Data::from_writer(ZeroizingWriter(&mut decrypted)).map_err(|e| e.error())?;
+        self.ctx.decrypt(data, output)?;
         Ok(decrypted)
     }
 }
+
+// Collects decrypted output without leaving copies behind when it grows
+struct ZeroizingWriter<'a>(&'a mut Zeroizing<Vec<u8>>);
+
+impl Write for ZeroizingWriter<'_> {
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        secret::extend_zeroized(self.0, bytes);
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
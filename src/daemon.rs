@@ -1,91 +1,220 @@
-use crate::db::Database;
+use crate::audit::{Actor, AuditAction, AuditLog};
+use crate::db::{self, Database, SecretInfo, SecretMeta};
 use crate::error::{Result, SvenError};
+use crate::logging;
+use crate::protocol;
+use crate::secret::SecretString;
 use daemonize::Daemonize;
 use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::fs::{File, OpenOptions};
 use std::io::{BufRead, BufReader, Write};
-use std::os::unix::net::{UnixListener, UnixStream};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt, PermissionsExt};
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
-use tokio::sync::mpsc;
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
+use tokio::net::{UnixListener, UnixStream};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{mpsc, oneshot, watch, Semaphore};
+use tokio::task::JoinSet;
+use tokio::time::timeout;
+
+// Names of the socket and PID file in the runtime directory
+const SOCKET_FILE: &str = "sven.sock";
+const PID_FILE: &str = "sven.pid";
+
+// Connections served at once
+const MAX_CONNECTIONS: usize = 32;
+
+// How long a client may take to send its handshake or next request
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+// How long a single request may take, including a GPG passphrase prompt
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(120);
+
+// How often the database is checked for changes made by other processes
+const CHANGE_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+// How long shutdown waits for in-flight requests
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Digest of each exported secret's value, keyed by name
+pub type SecretDigests = Vec<(String, u64)>;
 
 // Commands that can be sent to the daemon
 #[derive(Serialize, Deserialize, Debug)]
 pub enum DaemonCommand {
-    GetSecrets { shell: String },
-    AddSecret { key: String, value: String },
-    RemoveSecret { key: String },
-    ListSecrets,
+    GetSecrets { profile: Option<String>, dir: String },
+    GetSecret { key: String, profile: Option<String>, dir: String },
+    // Like GetSecrets, but only returns values whose digest differs from `known`
+    GetSecretChanges { profile: Option<String>, dir: String, known: SecretDigests },
+    AddSecret { profile: Option<String>, key: String, value: SecretString, scope: String, meta: SecretMeta },
+    RemoveSecret { profile: Option<String>, key: String, scope: String },
+    Rollback { profile: Option<String>, key: String, scope: String, version: u32 },
+    ListSecrets { profile: Option<String>, tag: Option<String> },
+    ListProfiles,
+    CreateProfile { name: String },
+    UseProfile { name: String },
+    DeleteProfile { name: String },
+    Status,
+    Unlock { timeouts: LockTimeouts },
+    Lock,
+    Reload,
     Shutdown,
 }
 
+// A command tagged with an ID that the daemon echoes in its response
+#[derive(Serialize, Deserialize, Debug)]
+struct Request {
+    id: u64,
+    command: DaemonCommand,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Response {
+    id: u64,
+    response: DaemonResponse,
+}
+
 // Responses from the daemon
 #[derive(Serialize, Deserialize, Debug)]
 pub enum DaemonResponse {
-    Secrets(Vec<(String, String)>),
-    KeyList(Vec<String>),
+    Secrets(Vec<(String, SecretString)>),
+    Secret(Option<SecretString>),
+    // Digests of every secret for the directory, and the values that changed
+    SecretChanges(SecretDigests, Vec<(String, SecretString)>),
+    SecretList(Vec<SecretInfo>),
+    Profiles(Vec<String>, String),
+    Status(DaemonStatus),
     Success(String),
     Error(String),
 }
 
+// When the daemon drops its cached secrets, in seconds; None never expires
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+pub struct LockTimeouts {
+    // Since the last request that used the cache
+    pub idle: Option<u64>,
+    // Since the cache was unlocked, regardless of use
+    pub absolute: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DaemonStatus {
+    pub locked: bool,
+    // Seconds until each timeout locks the cache
+    pub idle_remaining: Option<u64>,
+    pub absolute_remaining: Option<u64>,
+}
+
 pub struct Daemon;
 
 impl Daemon {
+    // Private directory holding the socket and PID file: $XDG_RUNTIME_DIR/sven,
+    // or a per-user sven-<uid> directory in the temp dir when that is unset
+    fn get_runtime_dir() -> Result<PathBuf> {
+        let uid = current_uid();
+        let dir = match dirs::runtime_dir() {
+            Some(runtime_dir) => runtime_dir.join("sven"),
+            None => std::env::temp_dir().join(format!("sven-{}", uid)),
+        };
+
+        if let Err(e) = std::fs::DirBuilder::new().mode(0o700).create(&dir) {
+            if e.kind() != std::io::ErrorKind::AlreadyExists {
+                return Err(e.into());
+            }
+        }
+
+        // Never follow a symlink or trust a directory someone else created
+        let metadata = std::fs::symlink_metadata(&dir)?;
+        if !metadata.is_dir() || metadata.uid() != uid {
+            return Err(SvenError::ConfigError(format!(
+                "{} is not a directory owned by the current user",
+                dir.display()
+            )));
+        }
+        if metadata.mode() & 0o077 != 0 {
+            std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o700))?;
+        }
+        Ok(dir)
+    }
+
+    // Where this version's daemon creates its socket and PID file
+    fn daemon_socket_path() -> Result<PathBuf> {
+        Ok(Self::get_runtime_dir()?.join(SOCKET_FILE))
+    }
+
+    fn daemon_pid_file_path() -> Result<PathBuf> {
+        Ok(Self::get_runtime_dir()?.join(PID_FILE))
+    }
+
+    // Where to find a running daemon's socket and PID file. Daemons started
+    // before the private runtime directory keep them directly in
+    // $XDG_RUNTIME_DIR (or the temp dir), so those are tried next, as long as
+    // they belong to the current user.
+    fn find_runtime_file(name: &str) -> Result<PathBuf> {
+        let path = Self::get_runtime_dir()?.join(name);
+        if !path.exists() {
+            let legacy = dirs::runtime_dir().unwrap_or_else(std::env::temp_dir).join(name);
+            if std::fs::symlink_metadata(&legacy).is_ok_and(|m| m.uid() == current_uid()) {
+                return Ok(legacy);
+            }
+        }
+        Ok(path)
+    }
+
     fn get_socket_path() -> Result<PathBuf> {
-        dirs::runtime_dir()
-            .or_else(|| Some(std::env::temp_dir()))
-            .map(|mut p| {
-                p.push("sven.sock");
-                p
-            })
-            .ok_or_else(|| SvenError::ConfigError("Could not determine socket path".into()))
+        Self::find_runtime_file(SOCKET_FILE)
     }
 
     pub fn get_pid_file_path() -> Result<PathBuf> {
-        dirs::runtime_dir()
-            .or_else(|| Some(std::env::temp_dir()))
-            .map(|mut p| {
-                p.push("sven.pid");
-                p
-            })
-            .ok_or_else(|| SvenError::ConfigError("Could not determine pid file path".into()))
+        Self::find_runtime_file(PID_FILE)
+    }
+
+    // Connections are recorded here, next to the daemon and audit logs
+    fn get_access_log_path() -> Result<PathBuf> {
+        Ok(logging::state_dir()?.join("access.log"))
     }
 
     // Start the daemon process
-    pub fn start_daemon() -> Result<()> {
+    pub fn start_daemon(timeouts: LockTimeouts) -> Result<()> {
         // Check if daemon is already running
         if Self::is_daemon_running()? {
             return Err(SvenError::ConfigError("Daemon is already running".into()));
         }
 
         // Remove socket file if it exists
-        let socket_path = Self::get_socket_path()?;
+        let socket_path = Self::daemon_socket_path()?;
         if socket_path.exists() {
             std::fs::remove_file(&socket_path)?;
         }
 
-        let pid_file_path = Self::get_pid_file_path()?;
-        let stdout = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open("/tmp/sven-daemon.log")?;
-        let stderr = OpenOptions::new()
+        // Set up logging before forking so a bad SVEN_LOG is reported here
+        logging::init()?;
+
+        // The log goes to stderr, which is redirected with stdout to a file
+        // only the user can read
+        let pid_file_path = Self::daemon_pid_file_path()?;
+        let log_file = OpenOptions::new()
             .create(true)
             .append(true)
-            .open("/tmp/sven-daemon.err")?;
+            .mode(0o600)
+            .open(logging::daemon_log_path()?)?;
 
         let daemonize = Daemonize::new()
             .pid_file(pid_file_path)
             .working_directory("/tmp")
-            .stdout(stdout)
-            .stderr(stderr);
+            .stdout(log_file.try_clone()?)
+            .stderr(log_file);
 
         match daemonize.start() {
             Ok(_) => {
                 // We're in the daemon process now
-                if let Err(e) = Self::run_daemon() {
-                    eprintln!("Daemon error: {}", e);
+                log::info!("Daemon started with PID {}", std::process::id());
+                if let Err(e) = Self::run_daemon(timeouts) {
+                    log::error!("Daemon error: {}", e);
                     std::process::exit(1);
                 }
                 std::process::exit(0);
@@ -94,11 +223,10 @@ impl Daemon {
         }
     }
 
-    // Check if daemon is running
-    pub fn is_daemon_running() -> Result<bool> {
+    fn read_pid() -> Result<Option<u32>> {
         let pid_file_path = Self::get_pid_file_path()?;
         if !pid_file_path.exists() {
-            return Ok(false);
+            return Ok(None);
         }
 
         let file = File::open(pid_file_path)?;
@@ -109,219 +237,994 @@ impl Daemon {
         let pid = line.trim().parse::<u32>().map_err(|_| {
             SvenError::ConfigError("Invalid PID in PID file".into())
         })?;
+        Ok(Some(pid))
+    }
+
+    // Check if daemon is running. The PID file alone isn't trusted, since it
+    // outlives a killed daemon and its PID may be reused by another process.
+    pub fn is_daemon_running() -> Result<bool> {
+        let pid = match Self::read_pid()? {
+            Some(pid) => pid,
+            None => return Ok(false),
+        };
+        // SAFETY: signal 0 only checks that the process exists
+        let exists = unsafe { libc::kill(pid as libc::pid_t, 0) } == 0
+            || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM);
+        Ok(exists && Self::ping())
+    }
 
-        // Check if process with this PID exists
-        let proc_path = PathBuf::from(format!("/proc/{}", pid));
-        Ok(proc_path.exists())
+    // Whether a sven daemon of any protocol version answers on the socket.
+    // Daemons that predate the handshake close the connection or answer with
+    // something else, which still shows they are alive; only a refused
+    // connection or a timeout doesn't.
+    fn ping() -> bool {
+        let ping = || -> Result<()> {
+            let mut stream = std::os::unix::net::UnixStream::connect(Self::get_socket_path()?)?;
+            stream.set_read_timeout(Some(Duration::from_secs(2)))?;
+            protocol::write_handshake(&mut stream)?;
+            protocol::read_handshake(&mut BufReader::new(stream))?;
+            Ok(())
+        };
+        ping().is_ok()
+    }
+
+    // Stops the daemon with SIGTERM, for daemons that can't be reached over the socket
+    pub fn terminate() -> Result<()> {
+        let pid = Self::read_pid()?
+            .ok_or_else(|| SvenError::ConfigError("Daemon is not running".into()))?;
+        // SAFETY: kill has no memory-safety preconditions
+        if unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) } != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(())
     }
 
     // Run the daemon main loop
-    fn run_daemon() -> Result<()> {
-        // Initialize the daemon to get the initial secrets
-        let mut db = Database::new()?;
-        let secrets_vec = db.get_all_secrets()?;
-        
-        // Convert to a HashMap and wrap in thread-safe container
-        let mut secrets_map = HashMap::new();
-        for (key, value) in secrets_vec {
-            secrets_map.insert(key, value);
+    fn run_daemon(timeouts: LockTimeouts) -> Result<()> {
+        harden_process();
+
+        // Database thread - handles all operations that need the GPG context,
+        // which can't be moved between threads
+        let (db_tx, db_rx) = mpsc::channel(64);
+        let db_thread = std::thread::spawn(move || Self::run_db_thread(db_rx));
+
+        let runtime = tokio::runtime::Runtime::new()?;
+        let result = runtime.block_on(Self::serve(timeouts, db_tx.clone()));
+
+        // Send shutdown signal to database thread
+        let _ = db_tx.blocking_send(DbCommand::Shutdown);
+        let _ = db_thread.join();
+        if let Ok(pid_file_path) = Self::daemon_pid_file_path() {
+            let _ = std::fs::remove_file(pid_file_path);
         }
-        let secrets = Arc::new(Mutex::new(secrets_map));
-        
-        // Create the Unix socket
-        let socket_path = Self::get_socket_path()?;
-        let listener = UnixListener::bind(&socket_path)?;
-        
-        // Set up a channel for shutdown signaling
-        let (tx, mut rx) = mpsc::channel::<()>(1);
-        let tx_clone = tx.clone();
-        
-        // Create a channel for database operations
-        let (db_tx, db_rx) = std::sync::mpsc::channel();
-        
-        // Database thread - handles all operations that need the GPG context
-        // We create a new Database instance in this thread to avoid Send issues
-        std::thread::spawn(move || {
-            // Create a new Database instance in this thread
-            match Database::new() {
-                Ok(mut db) => {
-                    for cmd in db_rx {
-                        match cmd {
-                            DbCommand::AddSecret { key, value, resp } => {
-                                let result = db.add_secret(&key, &value)
-                                    .map(|_| format!("Added secret: {}", key));
-                                let _ = resp.send(result);
-                            },
-                            DbCommand::RemoveSecret { key, resp } => {
-                                let result = db.remove_secret(&key)
-                                    .map(|_| format!("Removed secret: {}", key));
-                                let _ = resp.send(result);
-                            },
-                            DbCommand::Shutdown => break,
-                        }
+        result
+    }
+
+    fn run_db_thread(mut db_rx: mpsc::Receiver<DbCommand>) {
+        let mut db = match Database::new() {
+            Ok(db) => db,
+            Err(e) => {
+                log::error!("Failed to create database in worker thread: {}", e);
+                return;
+            }
+        };
+        let mut sync = SyncState::default();
+        while let Some(cmd) = db_rx.blocking_recv() {
+            match cmd {
+                DbCommand::AddSecret { profile, key, value, scope, meta, resp } => {
+                    let result = db.add_secret(&profile, &key, value.expose(), &scope, &meta)
+                        .map(|_| format!("Added secret: {}", key));
+                    let _ = resp.send(result);
+                },
+                DbCommand::RemoveSecret { profile, key, scope, resp } => {
+                    let result = db.remove_secret(&profile, &key, &scope)
+                        .map(|_| format!("Removed secret: {}", key));
+                    let _ = resp.send(result);
+                },
+                DbCommand::Rollback { profile, key, scope, version, resp } => {
+                    let _ = resp.send(db.rollback(&profile, &key, &scope, version));
+                },
+                DbCommand::ListSecrets { profile, tag, resp } => {
+                    let _ = resp.send(db.list_secrets(&profile, tag.as_deref()));
+                },
+                DbCommand::TouchSecrets { profile, keys } => {
+                    if let Err(e) = db.touch_secrets(&profile, &keys) {
+                        log::warn!("Failed to record secret access: {}", e);
                     }
                 },
-                Err(e) => {
-                    eprintln!("Failed to create database in worker thread: {}", e);
+                DbCommand::CreateProfile { name, resp } => {
+                    let result = db.create_profile(&name)
+                        .map(|_| format!("Created profile: {}", name));
+                    let _ = resp.send(result);
+                },
+                DbCommand::UseProfile { name, resp } => {
+                    let result = db.use_profile(&name)
+                        .map(|_| format!("Switched to profile: {}", name));
+                    let _ = resp.send(result);
+                },
+                DbCommand::DeleteProfile { name, resp } => {
+                    let _ = resp.send(db.delete_profile(&name));
+                },
+                DbCommand::LoadProfiles { resp } => {
+                    let _ = resp.send(load_profiles(&mut db, &mut sync));
+                },
+                DbCommand::SyncChanges { force, resp } => {
+                    let _ = resp.send(sync_changes(&mut db, &mut sync, force));
+                },
+                DbCommand::Shutdown => break,
+            }
+        }
+    }
+
+    // Accepts connections until shutdown, then waits for in-flight requests
+    async fn serve(timeouts: LockTimeouts, db_tx: mpsc::Sender<DbCommand>) -> Result<()> {
+        // Initialize the daemon with the secrets of every profile
+        let (active_profile, profiles) = Self::run_db_command(&db_tx, |resp| DbCommand::LoadProfiles { resp })
+            .await
+            .map_err(SvenError::ConfigError)?;
+
+        let access_log = AccessLog::open(&Self::get_access_log_path()?)?;
+        let audit_log = AuditLog::open()?;
+        let mut terminate = signal(SignalKind::terminate())?;
+        let mut interrupt = signal(SignalKind::interrupt())?;
+        let mut hangup = signal(SignalKind::hangup())?;
+        let mut user_defined1 = signal(SignalKind::user_defined1())?;
+
+        // Create the Unix socket, readable only by the current user
+        let socket_path = Self::daemon_socket_path()?;
+        let listener = UnixListener::bind(&socket_path)?;
+        std::fs::set_permissions(&socket_path, std::fs::Permissions::from_mode(0o600))?;
+
+        let (shutdown_tx, mut shutdown_rx) = watch::channel(false);
+        let state = Arc::new(DaemonState {
+            secrets: Mutex::new(SecretCache {
+                active_profile,
+                profiles,
+                unlocked: Some(UnlockState::new(timeouts)),
+            }),
+            db_tx,
+            shutdown_tx,
+            access_log,
+            audit_log,
+        });
+
+        // Pick up changes other processes make to the database
+        let sync_state = state.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(CHANGE_POLL_INTERVAL);
+            loop {
+                interval.tick().await;
+                match Self::reload(&sync_state, false).await {
+                    Ok(Some(msg)) => log::info!("{}", msg),
+                    Ok(None) => {},
+                    Err(e) => log::error!("Failed to reload secrets: {}", e),
                 }
             }
         });
-        
-        // Handle client connections
-        let secrets_clone = secrets.clone();
-        let db_tx_clone = db_tx.clone();
-        std::thread::spawn(move || {
-            for stream in listener.incoming() {
-                match stream {
-                    Ok(stream) => {
-                        let secrets = secrets_clone.clone();
-                        let tx = tx_clone.clone();
-                        let db_tx = db_tx_clone.clone();
-                        std::thread::spawn(move || {
-                            if let Err(e) = Self::handle_client(stream, secrets, db_tx, tx) {
-                                eprintln!("Error handling client: {}", e);
-                            }
-                        });
-                    }
-                    Err(e) => {
-                        eprintln!("Error accepting connection: {}", e);
-                    }
+
+        // SIGTERM and SIGINT shut down, SIGHUP reloads secrets, SIGUSR1 locks the cache
+        let signal_state = state.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = terminate.recv() => {
+                        log::info!("Received SIGTERM; shutting down");
+                        signal_state.shutdown_tx.send_replace(true);
+                    },
+                    _ = interrupt.recv() => {
+                        log::info!("Received SIGINT; shutting down");
+                        signal_state.shutdown_tx.send_replace(true);
+                    },
+                    _ = hangup.recv() => match Self::reload(&signal_state, true).await {
+                        Ok(msg) => log::info!("{}", msg.unwrap_or_else(|| "Secrets are up to date".into())),
+                        Err(e) => log::error!("Failed to reload secrets: {}", e),
+                    },
+                    _ = user_defined1.recv() => {
+                        signal_state.secrets.lock().unwrap().lock();
+                        log::info!("Received SIGUSR1; secrets locked");
+                    },
                 }
             }
         });
-        
-        // Wait for shutdown signal
-        let rt = tokio::runtime::Runtime::new().unwrap();
-        rt.block_on(async {
-            rx.recv().await;
+
+        // Lock the cache once a timeout expires
+        let lock_state = state.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(1));
+            loop {
+                interval.tick().await;
+                let mut secrets_guard = lock_state.secrets.lock().unwrap();
+                if secrets_guard.unlocked.as_ref().is_some_and(UnlockState::expired) {
+                    secrets_guard.lock();
+                    log::info!("Cache timeout expired; secrets locked");
+                }
+            }
         });
-        
-        // Send shutdown signal to database thread
-        let _ = db_tx.send(DbCommand::Shutdown);
-        
-        // Clean up
-        if socket_path.exists() {
-            let _ = std::fs::remove_file(socket_path);
+
+        // Lock the cache along with the login session
+        #[cfg(target_os = "linux")]
+        {
+            let session_state = state.clone();
+            tokio::spawn(async move {
+                let watched = crate::logind::watch_session_lock(|| {
+                    session_state.secrets.lock().unwrap().lock();
+                    log::info!("Session locked; secrets locked");
+                })
+                .await;
+                if let Err(e) = watched {
+                    log::info!("Not locking with the session: {}", e);
+                }
+            });
         }
-        
+
+        let connection_slots = Arc::new(Semaphore::new(MAX_CONNECTIONS));
+        let mut handlers = JoinSet::new();
+        loop {
+            // Past the limit, new clients wait in the listen backlog
+            let slot = tokio::select! {
+                slot = connection_slots.clone().acquire_owned() => slot.expect("semaphore is never closed"),
+                _ = shutdown_rx.changed() => break,
+            };
+            let stream = tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        log::error!("Error accepting connection: {}", e);
+                        continue;
+                    }
+                },
+                _ = shutdown_rx.changed() => break,
+            };
+            while handlers.try_join_next().is_some() {}
+
+            let state = state.clone();
+            handlers.spawn(async move {
+                if let Err(e) = Self::handle_client(stream, &state).await {
+                    log::warn!("Error handling client: {}", e);
+                }
+                drop(slot);
+            });
+        }
+
+        // Stop accepting and let in-flight requests finish
+        drop(listener);
+        let _ = std::fs::remove_file(&socket_path);
+        let drained = tokio::time::timeout(DRAIN_TIMEOUT, async {
+            while handlers.join_next().await.is_some() {}
+        })
+        .await;
+        if drained.is_err() {
+            log::warn!("Shutting down with requests still in flight");
+        }
+        state.secrets.lock().unwrap().lock();
+        log::info!("Daemon stopped");
+
         Ok(())
     }
+}
+
+// Keeps other processes of the same user from reading the daemon's memory
+// through core dumps or ptrace
+fn harden_process() {
+    #[cfg(target_os = "linux")]
+    // SAFETY: PR_SET_DUMPABLE takes a plain integer argument
+    if unsafe { libc::prctl(libc::PR_SET_DUMPABLE, 0, 0, 0, 0) } != 0 {
+        log::warn!("Failed to disable core dumps: {}", std::io::Error::last_os_error());
+    }
+}
+
+pub fn current_uid() -> u32 {
+    // SAFETY: getuid has no preconditions and cannot fail
+    unsafe { libc::getuid() }
+}
+
+// Append-only record of every connection with the peer's PID and executable
+struct AccessLog {
+    file: Mutex<File>,
+}
+
+impl AccessLog {
+    fn open(path: &std::path::Path) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::DirBuilder::new().recursive(true).mode(0o700).create(parent)?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .mode(0o600)
+            .open(path)?;
+        Ok(Self { file: Mutex::new(file) })
+    }
+
+    // Logs the connection and returns the peer if it is the daemon's own user
+    fn check_peer(&self, stream: &UnixStream) -> Result<Option<Actor>> {
+        let cred = stream.peer_cred()?;
+        let actor = Actor::peer(cred.uid(), cred.pid());
+        let allowed = actor.uid == current_uid();
+        let line = format!(
+            "{} {} {}",
+            db::unix_now(),
+            if allowed { "accepted" } else { "rejected" },
+            actor.describe()
+        );
+        writeln!(self.file.lock().unwrap(), "{}", line)?;
+        if !allowed {
+            log::warn!("Rejected connection from {}", actor.describe());
+            return Ok(None);
+        }
+        Ok(Some(actor))
+    }
+}
+
+type ProfileSecrets = HashMap<String, HashMap<(String, String), SecretString>>;
+
+// What the cache was last loaded from, kept by the database thread so
+// changes can be found without decrypting every row again
+#[derive(Default)]
+struct SyncState {
+    data_version: i64,
+    file_id: (u64, u64),
+    // Ciphertext of each (profile, key, scope) row
+    ciphertexts: HashMap<(String, String, String), String>,
+}
+
+// Rows that differ between the database and the cache
+struct CacheChanges {
+    active_profile: String,
+    profiles: Vec<String>,
+    updated: Vec<(String, String, String, SecretString)>,
+    removed: Vec<(String, String, String)>,
+}
+
+// Decrypts every profile into maps keyed by (key, scope), along with the active profile
+fn load_profiles(db: &mut Database, sync: &mut SyncState) -> Result<(String, ProfileSecrets)> {
+    let data_version = db.data_version()?;
+    let file_id = Database::file_id()?;
+    let mut profiles: ProfileSecrets = db
+        .list_profiles()?
+        .into_iter()
+        .map(|profile| (profile, HashMap::new()))
+        .collect();
+    let mut ciphertexts = HashMap::new();
+    for (profile, key, scope, encrypted_value) in db.encrypted_rows()? {
+        let value = db.decrypt_string(&encrypted_value)?;
+        value.mlock();
+        profiles.entry(profile.clone()).or_default().insert((key.clone(), scope.clone()), value);
+        ciphertexts.insert((profile, key, scope), encrypted_value);
+    }
+    *sync = SyncState { data_version, file_id, ciphertexts };
+    Ok((db.active_profile()?, profiles))
+}
+
+// Decrypts only the rows whose ciphertext changed since the last load or
+// sync. Returns None without reading rows if the database looks untouched.
+fn sync_changes(db: &mut Database, sync: &mut SyncState, force: bool) -> Result<Option<CacheChanges>> {
+    // A replaced file (e.g. written by a sync tool) needs a new connection
+    let file_id = Database::file_id()?;
+    if file_id != sync.file_id {
+        *db = Database::new()?;
+    }
+    let data_version = db.data_version()?;
+    if !force && file_id == sync.file_id && data_version == sync.data_version {
+        return Ok(None);
+    }
+    // Recorded even if decrypting fails below, so a bad row isn't retried
+    // every poll; the old ciphertexts stay so the next change retries it
+    sync.file_id = file_id;
+    sync.data_version = data_version;
+
+    let mut updated = Vec::new();
+    let mut ciphertexts = HashMap::new();
+    for (profile, key, scope, encrypted_value) in db.encrypted_rows()? {
+        let id = (profile, key, scope);
+        if sync.ciphertexts.get(&id) != Some(&encrypted_value) {
+            let value = db.decrypt_string(&encrypted_value)?;
+            updated.push((id.0.clone(), id.1.clone(), id.2.clone(), value));
+        }
+        ciphertexts.insert(id, encrypted_value);
+    }
+    let removed = sync
+        .ciphertexts
+        .keys()
+        .filter(|id| !ciphertexts.contains_key(*id))
+        .cloned()
+        .collect();
+    sync.ciphertexts = ciphertexts;
+
+    Ok(Some(CacheChanges {
+        active_profile: db.active_profile()?,
+        profiles: db.list_profiles()?,
+        updated,
+        removed,
+    }))
+}
+
+// Decrypted secrets held by the daemon, per profile and keyed by (key, scope)
+struct SecretCache {
+    active_profile: String,
+    profiles: ProfileSecrets,
+    // None while locked, in which case `profiles` is empty
+    unlocked: Option<UnlockState>,
+}
+
+struct UnlockState {
+    timeouts: LockTimeouts,
+    unlocked_at: Instant,
+    last_used: Instant,
+}
+
+impl UnlockState {
+    fn new(timeouts: LockTimeouts) -> Self {
+        let now = Instant::now();
+        Self { timeouts, unlocked_at: now, last_used: now }
+    }
+
+    fn idle_remaining(&self) -> Option<Duration> {
+        self.timeouts.idle
+            .map(|idle| Duration::from_secs(idle).saturating_sub(self.last_used.elapsed()))
+    }
+
+    fn absolute_remaining(&self) -> Option<Duration> {
+        self.timeouts.absolute
+            .map(|absolute| Duration::from_secs(absolute).saturating_sub(self.unlocked_at.elapsed()))
+    }
+
+    fn expired(&self) -> bool {
+        [self.idle_remaining(), self.absolute_remaining()].contains(&Some(Duration::ZERO))
+    }
+}
+
+impl SecretCache {
+    // Drops every cached value, which zeroes it
+    fn lock(&mut self) {
+        self.profiles.clear();
+        self.unlocked = None;
+    }
 
+    // Does nothing while locked, so a write that finishes after the cache was
+    // locked can't refill it
+    fn insert(&mut self, profile: String, key: String, scope: String, value: SecretString) {
+        if self.unlocked.is_none() {
+            return;
+        }
+        value.mlock();
+        self.profiles.entry(profile).or_default().insert((key, scope), value);
     }
 
+    fn insert_profile(&mut self, name: String) {
+        if self.unlocked.is_some() {
+            self.profiles.entry(name).or_default();
+        }
+    }
+
+    // Returns how many cached values were changed and removed. Rows the
+    // daemon wrote itself are already cached and not counted.
+    fn apply(&mut self, changes: CacheChanges) -> (usize, usize) {
+        self.active_profile = changes.active_profile;
+        self.profiles.retain(|name, _| changes.profiles.contains(name));
+        for name in changes.profiles {
+            self.profiles.entry(name).or_default();
+        }
+        let mut removed = 0;
+        for (profile, key, scope) in changes.removed {
+            if let Some(profile_secrets) = self.profiles.get_mut(&profile) {
+                if profile_secrets.remove(&(key, scope)).is_some() {
+                    removed += 1;
+                }
+            }
+        }
+        let mut updated = 0;
+        for (profile, key, scope, value) in changes.updated {
+            let cached = self.profiles.get(&profile).and_then(|p| p.get(&(key.clone(), scope.clone())));
+            if cached != Some(&value) {
+                updated += 1;
+                self.insert(profile, key, scope, value);
+            }
+        }
+        (updated, removed)
+    }
+
+    fn status(&self) -> DaemonStatus {
+        DaemonStatus {
+            locked: self.unlocked.is_none(),
+            idle_remaining: self.unlocked.as_ref()
+                .and_then(UnlockState::idle_remaining)
+                .map(|d| d.as_secs()),
+            absolute_remaining: self.unlocked.as_ref()
+                .and_then(UnlockState::absolute_remaining)
+                .map(|d| d.as_secs()),
+        }
+    }
+
+    // Resolves an optional profile name the same way Database::resolve_profile does
+    fn resolve_profile(&self, profile: Option<String>) -> std::result::Result<String, String> {
+        let name = profile.unwrap_or_else(|| self.active_profile.clone());
+        if self.profiles.contains_key(&name) {
+            Ok(name)
+        } else {
+            Err(format!("Profile '{}' does not exist", name))
+        }
+    }
+}
+
+type DbResponder<T = String> = oneshot::Sender<crate::error::Result<T>>;
+
+// Shared by every connection handler
+struct DaemonState {
+    secrets: Mutex<SecretCache>,
+    db_tx: mpsc::Sender<DbCommand>,
+    shutdown_tx: watch::Sender<bool>,
+    access_log: AccessLog,
+    audit_log: AuditLog,
+}
+
 // Commands for the database thread
 enum DbCommand {
     AddSecret {
+        profile: String,
         key: String,
-        value: String,
-        resp: std::sync::mpsc::Sender<crate::error::Result<String>>,
+        value: SecretString,
+        scope: String,
+        meta: SecretMeta,
+        resp: DbResponder,
     },
     RemoveSecret {
+        profile: String,
         key: String,
-        resp: std::sync::mpsc::Sender<crate::error::Result<String>>,
+        scope: String,
+        resp: DbResponder,
+    },
+    // Responds with the restored value so the cache can be updated
+    Rollback {
+        profile: String,
+        key: String,
+        scope: String,
+        version: u32,
+        resp: DbResponder<SecretString>,
+    },
+    ListSecrets {
+        profile: String,
+        tag: Option<String>,
+        resp: DbResponder<Vec<SecretInfo>>,
+    },
+    // Fire-and-forget update of last_accessed for (key, scope) pairs
+    TouchSecrets {
+        profile: String,
+        keys: Vec<(String, String)>,
+    },
+    CreateProfile {
+        name: String,
+        resp: DbResponder,
+    },
+    UseProfile {
+        name: String,
+        resp: DbResponder,
+    },
+    // Responds with the (key, scope) of each deleted secret
+    DeleteProfile {
+        name: String,
+        resp: DbResponder<Vec<(String, String)>>,
+    },
+    // Decrypts every profile again when unlocking a locked cache
+    LoadProfiles {
+        resp: DbResponder<(String, ProfileSecrets)>,
+    },
+    SyncChanges {
+        force: bool,
+        resp: DbResponder<Option<CacheChanges>>,
     },
     Shutdown,
 }
 
 impl Daemon {
 
+    // Send a command to the database thread and wait for its result
+    async fn run_db_command<T>(
+        db_tx: &mpsc::Sender<DbCommand>,
+        command: impl FnOnce(DbResponder<T>) -> DbCommand,
+    ) -> std::result::Result<T, String> {
+        let (resp_tx, resp_rx) = oneshot::channel();
+        db_tx
+            .send(command(resp_tx))
+            .await
+            .map_err(|e| format!("Failed to communicate with database thread: {}", e))?;
+        match resp_rx.await {
+            Ok(Ok(msg)) => Ok(msg),
+            Ok(Err(e)) => Err(e.to_string()),
+            Err(e) => Err(format!("Failed to communicate with database thread: {}", e)),
+        }
+    }
+
+    // Updates the cache with rows other processes changed. Unless `force` is
+    // set, the database is only read when its change counter moved. Returns a
+    // description of what changed, or None if nothing did.
+    async fn reload(state: &DaemonState, force: bool) -> std::result::Result<Option<String>, String> {
+        if state.secrets.lock().unwrap().unlocked.is_none() {
+            return Ok(None);
+        }
+        let changes = match Self::run_db_command(&state.db_tx, |resp| DbCommand::SyncChanges { force, resp }).await? {
+            Some(changes) => changes,
+            None => return Ok(None),
+        };
+        let mut secrets_guard = state.secrets.lock().unwrap();
+        // Don't refill a cache that was locked while the database was read
+        if secrets_guard.unlocked.is_none() {
+            return Ok(None);
+        }
+        match secrets_guard.apply(changes) {
+            (0, 0) => Ok(None),
+            (updated, removed) => Ok(Some(format!(
+                "Reloaded {} changed and {} removed secrets",
+                updated, removed
+            ))),
+        }
+    }
+
+    // Records a client's access to a secret; a failure is logged but doesn't fail the request
+    fn audit(state: &DaemonState, actor: &Actor, action: AuditAction, profile: &str, key: &str, scope: Option<&str>) {
+        if let Err(e) = state.audit_log.record(actor, action, profile, key, scope) {
+            log::error!("Failed to write audit log: {}", e);
+        }
+    }
+
     // Handle a client connection
-    fn handle_client(
-        stream: UnixStream, 
-        secrets: Arc<Mutex<HashMap<String, String>>>,
-        db_tx: std::sync::mpsc::Sender<DbCommand>,
-        shutdown_tx: mpsc::Sender<()>
-    ) -> Result<()> {
-        let mut reader = BufReader::new(&stream);
-        let mut request = String::new();
-        reader.read_line(&mut request)?;
-        
-        let command: DaemonCommand = serde_json::from_str(&request)
-            .map_err(|e| SvenError::ConfigError(format!("Invalid command: {}", e)))?;
+    async fn handle_client(stream: UnixStream, state: &DaemonState) -> Result<()> {
+        // Only processes of the user who started the daemon may talk to it
+        let actor = match state.access_log.check_peer(&stream)? {
+            Some(actor) => actor,
+            None => return Ok(()),
+        };
+        log::debug!("Client connected: {}", actor.describe());
+
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = tokio::io::BufReader::new(reader);
+        let mut shutdown_rx = state.shutdown_tx.subscribe();
+
+        // A client that connects and never sends anything is dropped
+        let version = match timeout(IDLE_TIMEOUT, protocol::read_handshake_async(&mut reader)).await {
+            Ok(version) => version?,
+            Err(_) => return Ok(()),
+        };
+        if version.is_none() {
+            // Clients from before the handshake expect a JSON line
+            let response = DaemonResponse::Error(format!(
+                "The running daemon speaks protocol version {} and this sven is older; restart it with 'sven stop' and 'sven unlock'",
+                protocol::PROTOCOL_VERSION
+            ));
+            let line = format!("{}\n", serde_json::to_string(&response)?);
+            writer.write_all(line.as_bytes()).await?;
+            return Ok(());
+        }
+        // Answer with our own version either way so the client can report a mismatch
+        protocol::write_handshake_async(&mut writer).await?;
+        if version != Some(protocol::PROTOCOL_VERSION) {
+            return Ok(());
+        }
+
+        loop {
+            let request = tokio::select! {
+                request = timeout(IDLE_TIMEOUT, protocol::read_frame_async::<Request>(&mut reader)) => match request {
+                    Ok(request) => match request? {
+                        Some(request) => request,
+                        None => break,
+                    },
+                    Err(_) => break,
+                },
+                // Stop taking new requests once shutting down
+                _ = shutdown_rx.changed() => break,
+            };
+
+            let response = timeout(REQUEST_TIMEOUT, Self::handle_command(request.command, state, &actor))
+                .await
+                .unwrap_or_else(|_| DaemonResponse::Error("Request timed out".into()));
+            let response = Response { id: request.id, response };
+            timeout(IDLE_TIMEOUT, protocol::write_frame_async(&mut writer, &response))
+                .await
+                .map_err(|_| SvenError::ConfigError("Timed out writing response".into()))??;
+        }
+
+        Ok(())
+    }
+
+    async fn handle_command(command: DaemonCommand, state: &DaemonState, actor: &Actor) -> DaemonResponse {
+        let secrets = &state.secrets;
+        let db_tx = &state.db_tx;
+
+        // Every other command needs the cache and counts as activity for the idle timeout
+        if !matches!(
+            command,
+            DaemonCommand::Status
+                | DaemonCommand::Unlock { .. }
+                | DaemonCommand::Lock
+                | DaemonCommand::Reload
+                | DaemonCommand::Shutdown
+        ) {
+            match secrets.lock().unwrap().unlocked.as_mut() {
+                Some(state) => state.last_used = Instant::now(),
+                None => return DaemonResponse::Error("Daemon is locked; run 'sven unlock'".into()),
+            }
+        }
         
-        let response = match command {
-            DaemonCommand::GetSecrets { shell: _ } => {
+        match command {
+            DaemonCommand::GetSecrets { profile, dir } => {
+                let secrets_guard = secrets.lock().unwrap();
+                match secrets_guard.resolve_profile(profile) {
+                    Ok(profile) => {
+                        let rows = secrets_guard.profiles[&profile].iter()
+                            .map(|((k, s), v)| (k.clone(), s.clone(), (s.clone(), v.clone())));
+                        let resolved = db::resolve_scopes(rows, std::path::Path::new(&dir));
+                        let keys: Vec<(String, String)> = resolved.iter()
+                            .map(|(k, (s, _))| (k.clone(), s.clone()))
+                            .collect();
+                        drop(secrets_guard);
+                        for (key, scope) in &keys {
+                            Self::audit(state, actor, AuditAction::Read, &profile, key, Some(scope));
+                        }
+                        let _ = db_tx.try_send(DbCommand::TouchSecrets { profile, keys });
+                        DaemonResponse::Secrets(resolved.into_iter().map(|(k, (_, v))| (k, v)).collect())
+                    },
+                    Err(e) => DaemonResponse::Error(e),
+                }
+            },
+            DaemonCommand::GetSecretChanges { profile, dir, known } => {
                 let secrets_guard = secrets.lock().unwrap();
-                let secrets_vec: Vec<(String, String)> = secrets_guard.iter()
-                    .map(|(k, v)| (k.clone(), v.clone()))
-                    .collect();
-                DaemonResponse::Secrets(secrets_vec)
+                match secrets_guard.resolve_profile(profile) {
+                    Ok(profile) => {
+                        let rows = secrets_guard.profiles[&profile].iter()
+                            .map(|((k, s), v)| (k.clone(), s.clone(), (s.clone(), v.clone())));
+                        let resolved = db::resolve_scopes(rows, std::path::Path::new(&dir));
+                        drop(secrets_guard);
+                        let known: HashMap<String, u64> = known.into_iter().collect();
+                        let digests = resolved.iter()
+                            .map(|(k, (_, v))| (k.clone(), v.digest()))
+                            .collect();
+                        // Unchanged values aren't sent, so they don't count as accessed
+                        let changed: Vec<_> = resolved.into_iter()
+                            .filter(|(k, (_, v))| known.get(k) != Some(&v.digest()))
+                            .collect();
+                        for (key, (scope, _)) in &changed {
+                            Self::audit(state, actor, AuditAction::Read, &profile, key, Some(scope));
+                        }
+                        let keys = changed.iter().map(|(k, (s, _))| (k.clone(), s.clone())).collect();
+                        let _ = db_tx.try_send(DbCommand::TouchSecrets { profile, keys });
+                        DaemonResponse::SecretChanges(digests, changed.into_iter().map(|(k, (_, v))| (k, v)).collect())
+                    },
+                    Err(e) => DaemonResponse::Error(e),
+                }
             },
-            DaemonCommand::ListSecrets => {
+            DaemonCommand::GetSecret { key, profile, dir } => {
                 let secrets_guard = secrets.lock().unwrap();
-                let keys: Vec<String> = secrets_guard.keys()
-                    .cloned()
-                    .collect();
-                DaemonResponse::KeyList(keys)
+                match secrets_guard.resolve_profile(profile) {
+                    Ok(profile) => {
+                        let rows = secrets_guard.profiles[&profile].iter()
+                            .filter(|((k, _), _)| *k == key)
+                            .map(|((k, s), v)| (k.clone(), s.clone(), (s.clone(), v.clone())));
+                        let value = db::resolve_scopes(rows, std::path::Path::new(&dir))
+                            .pop()
+                            .map(|(key, (scope, value))| {
+                                Self::audit(state, actor, AuditAction::Read, &profile, &key, Some(&scope));
+                                let _ = db_tx.try_send(DbCommand::TouchSecrets { profile, keys: vec![(key, scope)] });
+                                value
+                            });
+                        DaemonResponse::Secret(value)
+                    },
+                    Err(e) => DaemonResponse::Error(e),
+                }
             },
-            DaemonCommand::AddSecret { key, value } => {
-                // Create a channel for the response
-                let (resp_tx, resp_rx) = std::sync::mpsc::channel();
-                
-                // Send the command to the database thread
-                db_tx.send(DbCommand::AddSecret {
-                    key: key.clone(),
-                    value: value.clone(),
-                    resp: resp_tx,
-                })?;
-                
-                // Wait for the response
-                match resp_rx.recv() {
-                    Ok(Ok(msg)) => {
+            DaemonCommand::ListSecrets { profile, tag } => {
+                // Metadata isn't cached, so listing goes to the database
+                let resolved = secrets.lock().unwrap().resolve_profile(profile);
+                let result = match resolved {
+                    Ok(profile) => Self::run_db_command(db_tx, |resp| DbCommand::ListSecrets { profile, tag, resp }).await,
+                    Err(e) => Err(e),
+                };
+                match result {
+                    Ok(secrets) => DaemonResponse::SecretList(secrets),
+                    Err(e) => DaemonResponse::Error(format!("Failed to list secrets: {}", e)),
+                }
+            },
+            DaemonCommand::AddSecret { profile, key, value, scope, meta } => {
+                // Checked here too so a bad key never reaches the cache
+                if let Err(e) = db::validate_key(&key) {
+                    return DaemonResponse::Error(format!("Failed to add secret: {}", e));
+                }
+                let resolved = secrets.lock().unwrap().resolve_profile(profile);
+                let result = match resolved {
+                    Ok(profile) => Self::run_db_command(db_tx, |resp| DbCommand::AddSecret {
+                        profile: profile.clone(),
+                        key: key.clone(),
+                        value: value.clone(),
+                        scope: scope.clone(),
+                        meta,
+                        resp,
+                    })
+                    .await
+                    .map(|msg| (profile, msg)),
+                    Err(e) => Err(e),
+                };
+                match result {
+                    Ok((profile, msg)) => {
+                        Self::audit(state, actor, AuditAction::Add, &profile, &key, Some(&scope));
                         // Update the in-memory cache
-                        let mut secrets_guard = secrets.lock().unwrap();
-                        secrets_guard.insert(key, value);
+                        secrets.lock().unwrap().insert(profile, key, scope, value);
                         DaemonResponse::Success(msg)
                     },
-                    Ok(Err(e)) => DaemonResponse::Error(format!("Failed to add secret: {}", e)),
-                    Err(e) => DaemonResponse::Error(format!("Failed to communicate with database thread: {}", e)),
+                    Err(e) => DaemonResponse::Error(format!("Failed to add secret: {}", e)),
                 }
             },
-            DaemonCommand::RemoveSecret { key } => {
-                // Create a channel for the response
-                let (resp_tx, resp_rx) = std::sync::mpsc::channel();
-                
-                // Send the command to the database thread
-                db_tx.send(DbCommand::RemoveSecret {
-                    key: key.clone(),
-                    resp: resp_tx,
-                })?;
-                
-                // Wait for the response
-                match resp_rx.recv() {
-                    Ok(Ok(msg)) => {
+            DaemonCommand::RemoveSecret { profile, key, scope } => {
+                let resolved = secrets.lock().unwrap().resolve_profile(profile);
+                let result = match resolved {
+                    Ok(profile) => Self::run_db_command(db_tx, |resp| DbCommand::RemoveSecret {
+                        profile: profile.clone(),
+                        key: key.clone(),
+                        scope: scope.clone(),
+                        resp,
+                    })
+                    .await
+                    .map(|msg| (profile, msg)),
+                    Err(e) => Err(e),
+                };
+                match result {
+                    Ok((profile, msg)) => {
+                        Self::audit(state, actor, AuditAction::Remove, &profile, &key, Some(&scope));
                         // Update the in-memory cache
                         let mut secrets_guard = secrets.lock().unwrap();
-                        secrets_guard.remove(&key);
+                        if let Some(profile_secrets) = secrets_guard.profiles.get_mut(&profile) {
+                            profile_secrets.remove(&(key, scope));
+                        }
+                        DaemonResponse::Success(msg)
+                    },
+                    Err(e) => DaemonResponse::Error(format!("Failed to remove secret: {}", e)),
+                }
+            },
+            DaemonCommand::Rollback { profile, key, scope, version } => {
+                let resolved = secrets.lock().unwrap().resolve_profile(profile);
+                let result = match resolved {
+                    Ok(profile) => Self::run_db_command(db_tx, |resp| DbCommand::Rollback {
+                        profile: profile.clone(),
+                        key: key.clone(),
+                        scope: scope.clone(),
+                        version,
+                        resp,
+                    })
+                    .await
+                    .map(|value| (profile, value)),
+                    Err(e) => Err(e),
+                };
+                match result {
+                    Ok((profile, value)) => {
+                        Self::audit(state, actor, AuditAction::Rollback, &profile, &key, Some(&scope));
+                        // Update the in-memory cache
+                        secrets.lock().unwrap().insert(profile, key.clone(), scope, value);
+                        DaemonResponse::Success(format!("Rolled back {} to version {}", key, version))
+                    },
+                    Err(e) => DaemonResponse::Error(format!("Failed to roll back secret: {}", e)),
+                }
+            },
+            DaemonCommand::ListProfiles => {
+                let secrets_guard = secrets.lock().unwrap();
+                let mut names: Vec<String> = secrets_guard.profiles.keys().cloned().collect();
+                names.sort();
+                DaemonResponse::Profiles(names, secrets_guard.active_profile.clone())
+            },
+            DaemonCommand::CreateProfile { name } => {
+                match Self::run_db_command(db_tx, |resp| DbCommand::CreateProfile { name: name.clone(), resp }).await {
+                    Ok(msg) => {
+                        secrets.lock().unwrap().insert_profile(name);
+                        DaemonResponse::Success(msg)
+                    },
+                    Err(e) => DaemonResponse::Error(format!("Failed to create profile: {}", e)),
+                }
+            },
+            DaemonCommand::UseProfile { name } => {
+                match Self::run_db_command(db_tx, |resp| DbCommand::UseProfile { name: name.clone(), resp }).await {
+                    Ok(msg) => {
+                        secrets.lock().unwrap().active_profile = name;
                         DaemonResponse::Success(msg)
                     },
-                    Ok(Err(e)) => DaemonResponse::Error(format!("Failed to remove secret: {}", e)),
-                    Err(e) => DaemonResponse::Error(format!("Failed to communicate with database thread: {}", e)),
+                    Err(e) => DaemonResponse::Error(format!("Failed to switch profile: {}", e)),
+                }
+            },
+            DaemonCommand::DeleteProfile { name } => {
+                match Self::run_db_command(db_tx, |resp| DbCommand::DeleteProfile { name: name.clone(), resp }).await {
+                    Ok(deleted) => {
+                        for (key, scope) in &deleted {
+                            Self::audit(state, actor, AuditAction::Remove, &name, key, Some(scope));
+                        }
+                        secrets.lock().unwrap().profiles.remove(&name);
+                        DaemonResponse::Success(format!("Deleted profile: {}", name))
+                    },
+                    Err(e) => DaemonResponse::Error(format!("Failed to delete profile: {}", e)),
+                }
+            },
+            DaemonCommand::Status => DaemonResponse::Status(secrets.lock().unwrap().status()),
+            DaemonCommand::Unlock { timeouts } => {
+                let locked = secrets.lock().unwrap().unlocked.is_none();
+                // An unlocked cache keeps its secrets and only restarts its timers
+                let loaded = if locked {
+                    Self::run_db_command(db_tx, |resp| DbCommand::LoadProfiles { resp }).await.map(Some)
+                } else {
+                    Ok(None)
+                };
+                match loaded {
+                    Ok(loaded) => {
+                        let mut secrets_guard = secrets.lock().unwrap();
+                        if let Some((active_profile, profiles)) = loaded {
+                            secrets_guard.lock();
+                            secrets_guard.active_profile = active_profile;
+                            secrets_guard.profiles = profiles;
+                        }
+                        secrets_guard.unlocked = Some(UnlockState::new(timeouts));
+                        DaemonResponse::Success("Secrets are now unlocked and cached in memory.".into())
+                    },
+                    Err(e) => DaemonResponse::Error(format!("Failed to unlock: {}", e)),
+                }
+            },
+            DaemonCommand::Lock => {
+                secrets.lock().unwrap().lock();
+                DaemonResponse::Success("Secrets locked".into())
+            },
+            DaemonCommand::Reload => {
+                if secrets.lock().unwrap().unlocked.is_none() {
+                    return DaemonResponse::Error("Daemon is locked; run 'sven unlock'".into());
+                }
+                match Self::reload(state, true).await {
+                    Ok(msg) => DaemonResponse::Success(msg.unwrap_or_else(|| "Secrets are up to date".into())),
+                    Err(e) => DaemonResponse::Error(format!("Failed to reload secrets: {}", e)),
                 }
             },
             DaemonCommand::Shutdown => {
-                let _ = shutdown_tx.blocking_send(());
+                state.shutdown_tx.send_replace(true);
                 DaemonResponse::Success("Daemon shutting down".into())
             }
-        };
-        
-        let response_json = serde_json::to_string(&response)?;
-        let mut writer = &stream;
-        writeln!(writer, "{}", response_json)?;
-        
-        Ok(())
+        }
     }
 }
 
 // Client for communicating with the daemon
 pub struct DaemonClient {
     socket_path: PathBuf,
+    // Opened on the first command and reused for the ones after it
+    connection: RefCell<Option<Connection>>,
+}
+
+struct Connection {
+    reader: BufReader<std::os::unix::net::UnixStream>,
+    next_id: u64,
+}
+
+impl Connection {
+    fn open(socket_path: &std::path::Path) -> Result<Self> {
+        let mut stream = std::os::unix::net::UnixStream::connect(socket_path)?;
+        protocol::write_handshake(&mut stream)?;
+        let mut reader = BufReader::new(stream);
+        match protocol::read_handshake(&mut reader)? {
+            Some(protocol::PROTOCOL_VERSION) => Ok(Self { reader, next_id: 1 }),
+            version => Err(protocol::mismatch_error(version)),
+        }
+    }
+
+    fn request(&mut self, command: DaemonCommand) -> Result<DaemonResponse> {
+        let id = self.next_id;
+        self.next_id += 1;
+        protocol::write_frame(&mut self.reader.get_ref(), &Request { id, command })?;
+        let response: Response = protocol::read_frame(&mut self.reader)?
+            .ok_or_else(|| SvenError::ConfigError("Daemon closed the connection".into()))?;
+        if response.id != id {
+            return Err(SvenError::ConfigError(format!(
+                "Daemon answered request {} instead of {}",
+                response.id, id
+            )));
+        }
+        Ok(response.response)
+    }
 }
 
 impl DaemonClient {
     pub fn new() -> Result<Self> {
         let socket_path = Daemon::get_socket_path()?;
-        Ok(Self { socket_path })
+        Ok(Self { socket_path, connection: RefCell::new(None) })
     }
     
     pub fn is_daemon_running() -> Result<bool> {
@@ -334,43 +1237,88 @@ impl DaemonClient {
             return Err(SvenError::ConfigError("Daemon is not running".into()));
         }
         
-        let mut stream = UnixStream::connect(&self.socket_path)?;
-        let command_json = serde_json::to_string(&command)?;
-        writeln!(stream, "{}", command_json)?;
-        
-        let mut reader = BufReader::new(stream);
-        let mut response = String::new();
-        reader.read_line(&mut response)?;
-        
-        let response: DaemonResponse = serde_json::from_str(&response)
-            .map_err(|e| SvenError::ConfigError(format!("Invalid response: {}", e)))?;
-            
-        Ok(response)
+        let mut connection = self.connection.borrow_mut();
+        if connection.is_none() {
+            *connection = Some(Connection::open(&self.socket_path)?);
+        }
+        let result = connection.as_mut().unwrap().request(command);
+        // Don't reuse a connection that may be out of step
+        if result.is_err() {
+            *connection = None;
+        }
+        result
     }
     
-    // Get all secrets from the daemon
-    pub fn get_secrets(&self, shell: &str) -> Result<Vec<(String, String)>> {
-        match self.send_command(DaemonCommand::GetSecrets { shell: shell.to_string() })? {
+    // Get the secrets that apply in `dir` from the daemon
+    pub fn get_secrets(&self, profile: Option<&str>, dir: &str) -> Result<Vec<(String, SecretString)>> {
+        match self.send_command(DaemonCommand::GetSecrets {
+            profile: profile.map(str::to_string),
+            dir: dir.to_string(),
+        })? {
             DaemonResponse::Secrets(secrets) => Ok(secrets),
             DaemonResponse::Error(e) => Err(SvenError::ConfigError(e)),
             _ => Err(SvenError::ConfigError("Unexpected response from daemon".into())),
         }
     }
     
+    // Digests of the secrets for `dir` and the values that differ from `known`
+    pub fn get_secret_changes(
+        &self,
+        profile: Option<&str>,
+        dir: &str,
+        known: &[(String, u64)],
+    ) -> Result<(SecretDigests, Vec<(String, SecretString)>)> {
+        match self.send_command(DaemonCommand::GetSecretChanges {
+            profile: profile.map(str::to_string),
+            dir: dir.to_string(),
+            known: known.to_vec(),
+        })? {
+            DaemonResponse::SecretChanges(digests, changed) => Ok((digests, changed)),
+            DaemonResponse::Error(e) => Err(SvenError::ConfigError(e)),
+            _ => Err(SvenError::ConfigError("Unexpected response from daemon".into())),
+        }
+    }
+    
+    // Get a single secret as seen from `dir` from the daemon
+    pub fn get_secret(&self, key: &str, profile: Option<&str>, dir: &str) -> Result<Option<SecretString>> {
+        match self.send_command(DaemonCommand::GetSecret {
+            key: key.to_string(),
+            profile: profile.map(str::to_string),
+            dir: dir.to_string(),
+        })? {
+            DaemonResponse::Secret(value) => Ok(value),
+            DaemonResponse::Error(e) => Err(SvenError::ConfigError(e)),
+            _ => Err(SvenError::ConfigError("Unexpected response from daemon".into())),
+        }
+    }
+    
     // List all secret keys from the daemon
-    pub fn list_secrets(&self) -> Result<Vec<String>> {
-        match self.send_command(DaemonCommand::ListSecrets)? {
-            DaemonResponse::KeyList(keys) => Ok(keys),
+    pub fn list_secrets(&self, profile: Option<&str>, tag: Option<&str>) -> Result<Vec<SecretInfo>> {
+        match self.send_command(DaemonCommand::ListSecrets {
+            profile: profile.map(str::to_string),
+            tag: tag.map(str::to_string),
+        })? {
+            DaemonResponse::SecretList(secrets) => Ok(secrets),
             DaemonResponse::Error(e) => Err(SvenError::ConfigError(e)),
             _ => Err(SvenError::ConfigError("Unexpected response from daemon".into())),
         }
     }
     
     // Add a secret through the daemon
-    pub fn add_secret(&self, key: &str, value: &str) -> Result<String> {
+    pub fn add_secret(
+        &self,
+        profile: Option<&str>,
+        key: &str,
+        value: &SecretString,
+        scope: &str,
+        meta: &SecretMeta,
+    ) -> Result<String> {
         match self.send_command(DaemonCommand::AddSecret { 
+            profile: profile.map(str::to_string),
             key: key.to_string(), 
-            value: value.to_string() 
+            value: value.clone(),
+            scope: scope.to_string(),
+            meta: meta.clone(),
         })? {
             DaemonResponse::Success(msg) => Ok(msg),
             DaemonResponse::Error(e) => Err(SvenError::ConfigError(e)),
@@ -379,8 +1327,98 @@ impl DaemonClient {
     }
     
     // Remove a secret through the daemon
-    pub fn remove_secret(&self, key: &str) -> Result<String> {
-        match self.send_command(DaemonCommand::RemoveSecret { key: key.to_string() })? {
+    pub fn remove_secret(&self, profile: Option<&str>, key: &str, scope: &str) -> Result<String> {
+        match self.send_command(DaemonCommand::RemoveSecret {
+            profile: profile.map(str::to_string),
+            key: key.to_string(),
+            scope: scope.to_string(),
+        })? {
+            DaemonResponse::Success(msg) => Ok(msg),
+            DaemonResponse::Error(e) => Err(SvenError::ConfigError(e)),
+            _ => Err(SvenError::ConfigError("Unexpected response from daemon".into())),
+        }
+    }
+    
+    // Restore a previous value of a secret through the daemon
+    pub fn rollback(&self, profile: Option<&str>, key: &str, scope: &str, version: u32) -> Result<String> {
+        match self.send_command(DaemonCommand::Rollback {
+            profile: profile.map(str::to_string),
+            key: key.to_string(),
+            scope: scope.to_string(),
+            version,
+        })? {
+            DaemonResponse::Success(msg) => Ok(msg),
+            DaemonResponse::Error(e) => Err(SvenError::ConfigError(e)),
+            _ => Err(SvenError::ConfigError("Unexpected response from daemon".into())),
+        }
+    }
+    
+    // List profiles and the active one through the daemon
+    pub fn list_profiles(&self) -> Result<(Vec<String>, String)> {
+        match self.send_command(DaemonCommand::ListProfiles)? {
+            DaemonResponse::Profiles(names, active) => Ok((names, active)),
+            DaemonResponse::Error(e) => Err(SvenError::ConfigError(e)),
+            _ => Err(SvenError::ConfigError("Unexpected response from daemon".into())),
+        }
+    }
+    
+    // Create a profile through the daemon
+    pub fn create_profile(&self, name: &str) -> Result<String> {
+        match self.send_command(DaemonCommand::CreateProfile { name: name.to_string() })? {
+            DaemonResponse::Success(msg) => Ok(msg),
+            DaemonResponse::Error(e) => Err(SvenError::ConfigError(e)),
+            _ => Err(SvenError::ConfigError("Unexpected response from daemon".into())),
+        }
+    }
+    
+    // Switch the active profile through the daemon
+    pub fn use_profile(&self, name: &str) -> Result<String> {
+        match self.send_command(DaemonCommand::UseProfile { name: name.to_string() })? {
+            DaemonResponse::Success(msg) => Ok(msg),
+            DaemonResponse::Error(e) => Err(SvenError::ConfigError(e)),
+            _ => Err(SvenError::ConfigError("Unexpected response from daemon".into())),
+        }
+    }
+    
+    // Delete a profile through the daemon
+    pub fn delete_profile(&self, name: &str) -> Result<String> {
+        match self.send_command(DaemonCommand::DeleteProfile { name: name.to_string() })? {
+            DaemonResponse::Success(msg) => Ok(msg),
+            DaemonResponse::Error(e) => Err(SvenError::ConfigError(e)),
+            _ => Err(SvenError::ConfigError("Unexpected response from daemon".into())),
+        }
+    }
+    
+    // Whether the daemon holds secrets and how long until it locks them
+    pub fn status(&self) -> Result<DaemonStatus> {
+        match self.send_command(DaemonCommand::Status)? {
+            DaemonResponse::Status(status) => Ok(status),
+            DaemonResponse::Error(e) => Err(SvenError::ConfigError(e)),
+            _ => Err(SvenError::ConfigError("Unexpected response from daemon".into())),
+        }
+    }
+    
+    // Reload a locked cache, or restart the timeouts of an unlocked one
+    pub fn unlock(&self, timeouts: LockTimeouts) -> Result<String> {
+        match self.send_command(DaemonCommand::Unlock { timeouts })? {
+            DaemonResponse::Success(msg) => Ok(msg),
+            DaemonResponse::Error(e) => Err(SvenError::ConfigError(e)),
+            _ => Err(SvenError::ConfigError("Unexpected response from daemon".into())),
+        }
+    }
+    
+    // Pick up changes made to the database outside the daemon right away
+    pub fn reload(&self) -> Result<String> {
+        match self.send_command(DaemonCommand::Reload)? {
+            DaemonResponse::Success(msg) => Ok(msg),
+            DaemonResponse::Error(e) => Err(SvenError::ConfigError(e)),
+            _ => Err(SvenError::ConfigError("Unexpected response from daemon".into())),
+        }
+    }
+    
+    // Drop the cached secrets but keep the daemon running
+    pub fn lock(&self) -> Result<String> {
+        match self.send_command(DaemonCommand::Lock)? {
             DaemonResponse::Success(msg) => Ok(msg),
             DaemonResponse::Error(e) => Err(SvenError::ConfigError(e)),
             _ => Err(SvenError::ConfigError("Unexpected response from daemon".into())),
@@ -395,4 +1433,80 @@ impl DaemonClient {
             _ => Err(SvenError::ConfigError("Unexpected response from daemon".into())),
         }
     }
-}
\ No newline at end of file
+}
+
+// Parses durations such as "30m", "1h30m", "90s" or "2d"; a bare number is seconds
+pub fn parse_duration(input: &str) -> std::result::Result<Duration, String> {
+    let too_long = || format!("invalid duration '{}': too long", input);
+    let mut total = 0u64;
+    let mut digits = String::new();
+    for c in input.trim().chars() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
+        }
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            _ => return Err(format!("invalid duration '{}': unknown unit '{}'", input, c)),
+        };
+        if digits.is_empty() {
+            return Err(format!("invalid duration '{}': '{}' has no amount", input, c));
+        }
+        let amount: u64 = digits.parse().map_err(|_| too_long())?;
+        total = amount.checked_mul(unit).and_then(|secs| total.checked_add(secs)).ok_or_else(too_long)?;
+        digits.clear();
+    }
+    if !digits.is_empty() {
+        let amount: u64 = digits.parse().map_err(|_| too_long())?;
+        total = total.checked_add(amount).ok_or_else(too_long)?;
+    }
+    if total == 0 {
+        return Err(format!("invalid duration '{}': must be greater than zero", input));
+    }
+    Ok(Duration::from_secs(total))
+}
+
+// Formats seconds as "1h 5m 30s", leaving out leading zero units
+pub fn format_duration(secs: u64) -> String {
+    let (hours, minutes, seconds) = (secs / 3600, secs % 3600 / 60, secs % 60);
+    if hours > 0 {
+        format!("{}h {}m {}s", hours, minutes, seconds)
+    } else if minutes > 0 {
+        format!("{}m {}s", minutes, seconds)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_are_parsed() {
+        assert_eq!(parse_duration("30m"), Ok(Duration::from_secs(30 * 60)));
+        assert_eq!(parse_duration("8h"), Ok(Duration::from_secs(8 * 60 * 60)));
+        assert_eq!(parse_duration("1h30m"), Ok(Duration::from_secs(90 * 60)));
+        assert_eq!(parse_duration("2d"), Ok(Duration::from_secs(2 * 24 * 60 * 60)));
+        assert_eq!(parse_duration(" 90 "), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("1m30"), Ok(Duration::from_secs(90)));
+    }
+
+    #[test]
+    fn invalid_durations_are_rejected() {
+        for input in ["0", "0m", "", "m", "30x", "30 m", "-5m", "1.5h"] {
+            assert!(parse_duration(input).is_err(), "{:?} was accepted", input);
+        }
+    }
+
+    #[test]
+    fn overflowing_durations_are_rejected() {
+        for input in ["18446744073709551616", "18446744073709551616s", "18446744073709551615d", "18446744073709551615s1s"] {
+            let err = parse_duration(input).unwrap_err();
+            assert!(err.ends_with("too long"), "{:?}: {}", input, err);
+        }
+    }
+}
@@ -1,7 +1,46 @@
+use crate::backup::{self, Bundle, BundleEntry, RestoreMode, RestoreSummary, SecretId};
 use crate::crypto::CryptoManager;
 use crate::error::{Result, SvenError};
+use crate::migrations;
+use crate::secret::SecretString;
 use rusqlite::{params, Connection};
-use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use zeroize::Zeroizing;
+
+// Scope value stored for secrets that apply in every directory
+pub const GLOBAL_SCOPE: &str = "";
+
+// Profile that always exists and is active until another one is selected
+pub const DEFAULT_PROFILE: &str = "default";
+
+const ACTIVE_PROFILE_CONFIG: &str = "active_profile";
+
+// Number of previous values kept per secret
+const HISTORY_RETENTION_CONFIG: &str = "history_retention";
+const DEFAULT_HISTORY_RETENTION: u32 = 10;
+
+// Optional metadata given when adding a secret; `None` keeps what is stored
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct SecretMeta {
+    pub description: Option<String>,
+    pub tags: Option<Vec<String>>,
+}
+
+// Everything about a stored secret except its value; timestamps are
+// formatted as UTC "YYYY-MM-DD HH:MM:SS"
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SecretInfo {
+    pub key: String,
+    pub scope: String,
+    pub description: Option<String>,
+    pub tags: Vec<String>,
+    pub created_at: Option<String>,
+    pub updated_at: Option<String>,
+    pub last_accessed: Option<String>,
+}
 
 pub struct Database {
     conn: Connection,
@@ -16,10 +55,10 @@ impl Database {
             std::fs::create_dir_all(parent)?;
         }
 
-        let conn = Connection::open(&db_path)?;
+        let mut conn = Connection::open(&db_path)?;
+        migrations::run(&mut conn, &db_path)?;
         let crypto = CryptoManager::new()?;
         let mut db = Self { conn, crypto };
-        db.init()?;
         db.crypto.ensure_key_selected(&db.conn)?;
         Ok(db)
     }
@@ -34,69 +73,790 @@ impl Database {
             .ok_or_else(|| SvenError::ConfigError("Could not find config directory".into()))
     }
 
-    fn init(&self) -> Result<()> {
-        self.conn.execute(
-            "CREATE TABLE IF NOT EXISTS variables (
-                key TEXT PRIMARY KEY,
-                value TEXT NOT NULL
-            )",
-            [],
+    pub fn list_profiles(&self) -> Result<Vec<String>> {
+        let mut stmt = self.conn.prepare("SELECT name FROM profiles ORDER BY name")?;
+        let names = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<std::result::Result<Vec<String>, _>>()?;
+        Ok(names)
+    }
+
+    fn profile_exists(&self, name: &str) -> Result<bool> {
+        let count: i64 = self.conn.query_row(
+            "SELECT COUNT(*) FROM profiles WHERE name = ?1",
+            params![name],
+            |row| row.get(0),
         )?;
+        Ok(count > 0)
+    }
+
+    pub fn active_profile(&self) -> Result<String> {
+        let mut stmt = self.conn.prepare("SELECT value FROM config WHERE key = ?1")?;
+        let mut rows = stmt.query(params![ACTIVE_PROFILE_CONFIG])?;
+        match rows.next()? {
+            Some(row) => Ok(row.get(0)?),
+            None => Ok(DEFAULT_PROFILE.to_string()),
+        }
+    }
+
+    // Resolves an optional --profile argument to an existing profile name
+    pub fn resolve_profile(&self, profile: Option<&str>) -> Result<String> {
+        match profile {
+            None => self.active_profile(),
+            Some(name) if self.profile_exists(name)? => Ok(name.to_string()),
+            Some(name) => Err(SvenError::ConfigError(format!(
+                "Profile '{}' does not exist",
+                name
+            ))),
+        }
+    }
+
+    pub fn create_profile(&self, name: &str) -> Result<()> {
+        validate_profile_name(name)?;
+        if self.profile_exists(name)? {
+            return Err(SvenError::ConfigError(format!(
+                "Profile '{}' already exists",
+                name
+            )));
+        }
+        self.conn
+            .execute("INSERT INTO profiles (name) VALUES (?1)", params![name])?;
+        Ok(())
+    }
 
+    pub fn use_profile(&self, name: &str) -> Result<()> {
+        let name = self.resolve_profile(Some(name))?;
         self.conn.execute(
-            "CREATE TABLE IF NOT EXISTS config (
-                key TEXT PRIMARY KEY,
-                value TEXT NOT NULL
-            )",
-            [],
+            "INSERT OR REPLACE INTO config (key, value) VALUES (?1, ?2)",
+            params![ACTIVE_PROFILE_CONFIG, name],
         )?;
         Ok(())
     }
 
-    pub fn add_secret(&mut self, key: &str, value: &str) -> Result<()> {
-        let encrypted = self.crypto.encrypt(value.as_bytes())?;
-        self.conn.execute(
-            "INSERT OR REPLACE INTO variables (key, value) VALUES (?1, ?2)",
-            params![key, encrypted],
+    // Deletes a profile together with every secret stored in it; returns the
+    // (key, scope) of each deleted secret
+    pub fn delete_profile(&mut self, name: &str) -> Result<Vec<(String, String)>> {
+        let name = self.resolve_profile(Some(name))?;
+        if name == DEFAULT_PROFILE {
+            return Err(SvenError::ConfigError(format!(
+                "Cannot delete the '{}' profile",
+                name
+            )));
+        }
+        if name == self.active_profile()? {
+            return Err(SvenError::ConfigError(format!(
+                "Cannot delete the active profile '{}'",
+                name
+            )));
+        }
+        let tx = self.conn.transaction()?;
+        let deleted = tx
+            .prepare("SELECT key, scope FROM variables WHERE profile = ?1 ORDER BY key, scope")?
+            .query_map(params![name], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<std::result::Result<Vec<(String, String)>, _>>()?;
+        tx.execute("DELETE FROM variables WHERE profile = ?1", params![name])?;
+        tx.execute("DELETE FROM variable_history WHERE profile = ?1", params![name])?;
+        tx.execute("DELETE FROM profiles WHERE name = ?1", params![name])?;
+        tx.commit()?;
+        Ok(deleted)
+    }
+
+    pub fn add_secret(
+        &mut self,
+        profile: &str,
+        key: &str,
+        value: &[u8],
+        scope: &str,
+        meta: &SecretMeta,
+    ) -> Result<()> {
+        validate_key(key)?;
+        let tags = meta.tags.as_deref().map(join_tags).transpose()?;
+        // Another process may have changed the recipients since we opened the store
+        self.crypto.load_recipients(&self.conn)?;
+        let encrypted = self.crypto.encrypt(value)?;
+        let retention = self.history_retention()?;
+        let tx = self.conn.transaction()?;
+        record_history(&tx, profile, key, scope, retention)?;
+        tx.execute(
+            "INSERT INTO variables (profile, key, scope, value, description, tags, created_at, updated_at)
+                VALUES (?1, ?2, ?3, ?4, ?5, COALESCE(?6, ''), ?7, ?7)
+             ON CONFLICT (profile, key, scope) DO UPDATE SET
+                value = excluded.value,
+                description = COALESCE(?5, description),
+                tags = COALESCE(?6, tags),
+                updated_at = excluded.updated_at",
+            params![profile, key, scope, encrypted, meta.description, tags, unix_now()],
         )?;
+        tx.commit()?;
         Ok(())
     }
 
-    pub fn remove_secret(&self, key: &str) -> Result<()> {
-        self.conn
-            .execute("DELETE FROM variables WHERE key = ?1", params![key])?;
+    // Records that secrets were read, for `sven list --long`
+    pub fn touch_secrets(&self, profile: &str, keys: &[(String, String)]) -> Result<()> {
+        let now = unix_now();
+        for (key, scope) in keys {
+            self.conn.execute(
+                "UPDATE variables SET last_accessed = ?1 WHERE profile = ?2 AND key = ?3 AND scope = ?4",
+                params![now, profile, key, scope],
+            )?;
+        }
         Ok(())
     }
 
-    pub fn list_secrets(&self) -> Result<Vec<String>> {
-        let mut stmt = self
-            .conn
-            .prepare("SELECT key FROM variables ORDER BY key")?;
-        let keys = stmt
-            .query_map([], |row| row.get(0))?
+    // Removed values stay in the history, so a removal can be rolled back
+    pub fn remove_secret(&mut self, profile: &str, key: &str, scope: &str) -> Result<()> {
+        let retention = self.history_retention()?;
+        let tx = self.conn.transaction()?;
+        record_history(&tx, profile, key, scope, retention)?;
+        tx.execute(
+            "DELETE FROM variables WHERE profile = ?1 AND key = ?2 AND scope = ?3",
+            params![profile, key, scope],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn history_retention(&self) -> Result<u32> {
+        let mut stmt = self.conn.prepare("SELECT value FROM config WHERE key = ?1")?;
+        let mut rows = stmt.query(params![HISTORY_RETENTION_CONFIG])?;
+        match rows.next()? {
+            Some(row) => {
+                let value: String = row.get(0)?;
+                value.parse().map_err(|_| {
+                    SvenError::ConfigError(format!("Invalid {} in config: {}", HISTORY_RETENTION_CONFIG, value))
+                })
+            }
+            None => Ok(DEFAULT_HISTORY_RETENTION),
+        }
+    }
+
+    // Sets how many previous values are kept per secret, pruning older ones
+    pub fn set_history_retention(&mut self, retention: u32) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "INSERT OR REPLACE INTO config (key, value) VALUES (?1, ?2)",
+            params![HISTORY_RETENTION_CONFIG, retention.to_string()],
+        )?;
+        tx.execute(
+            "DELETE FROM variable_history WHERE id NOT IN (
+                SELECT id FROM (
+                    SELECT id, ROW_NUMBER() OVER (
+                        PARTITION BY profile, key, scope ORDER BY id DESC
+                    ) AS n FROM variable_history
+                ) WHERE n <= ?1
+            )",
+            params![retention],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    // Returns (version, replaced at) for the previous values of a secret,
+    // newest first; version 1 is the value replaced most recently
+    pub fn history(&self, profile: &str, key: &str, scope: &str) -> Result<Vec<(u32, String)>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT datetime(replaced_at, 'unixepoch') FROM variable_history
+             WHERE profile = ?1 AND key = ?2 AND scope = ?3 ORDER BY id DESC",
+        )?;
+        let times = stmt
+            .query_map(params![profile, key, scope], |row| row.get(0))?
             .collect::<std::result::Result<Vec<String>, _>>()?;
-        Ok(keys)
+        Ok(times
+            .into_iter()
+            .enumerate()
+            .map(|(i, time)| (i as u32 + 1, time))
+            .collect())
     }
 
-    pub fn get_all_secrets(&mut self) -> Result<Vec<(String, String)>> {
-        let mut stmt = self
-            .conn
-            .prepare("SELECT key, value FROM variables ORDER BY key")?;
-        let rows = stmt.query_map([], |row| {
+    // Makes a previous value current again and returns it. The value being
+    // replaced goes into the history, so a rollback can itself be undone.
+    pub fn rollback(&mut self, profile: &str, key: &str, scope: &str, version: u32) -> Result<SecretString> {
+        let encrypted_value: Option<String> = {
+            let mut stmt = self.conn.prepare(
+                "SELECT value FROM variable_history
+                 WHERE profile = ?1 AND key = ?2 AND scope = ?3
+                 ORDER BY id DESC LIMIT 1 OFFSET ?4",
+            )?;
+            let mut rows = stmt.query(params![profile, key, scope, version.saturating_sub(1)])?;
+            match rows.next()? {
+                Some(row) => Some(row.get(0)?),
+                None => None,
+            }
+        };
+        let encrypted_value = encrypted_value.filter(|_| version > 0).ok_or_else(|| {
+            SvenError::ConfigError(format!("No version {} in the history of {}", version, key))
+        })?;
+
+        // Re-encrypt in case the recipients changed since it was stored
+        let value = self.decrypt_string(&encrypted_value)?;
+        self.add_secret(profile, key, value.expose(), scope, &SecretMeta::default())?;
+        Ok(value)
+    }
+
+    // Lists the secrets of a profile, optionally only those carrying `tag`
+    pub fn list_secrets(&self, profile: &str, tag: Option<&str>) -> Result<Vec<SecretInfo>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT key, scope, description, tags,
+                    datetime(created_at, 'unixepoch'),
+                    datetime(updated_at, 'unixepoch'),
+                    datetime(last_accessed, 'unixepoch')
+             FROM variables WHERE profile = ?1 ORDER BY key, scope",
+        )?;
+        let secrets = stmt
+            .query_map(params![profile], |row| {
+                let tags: String = row.get(3)?;
+                Ok(SecretInfo {
+                    key: row.get(0)?,
+                    scope: row.get(1)?,
+                    description: row.get(2)?,
+                    tags: split_tags(&tags),
+                    created_at: row.get(4)?,
+                    updated_at: row.get(5)?,
+                    last_accessed: row.get(6)?,
+                })
+            })?
+            .collect::<std::result::Result<Vec<SecretInfo>, _>>()?;
+        Ok(secrets
+            .into_iter()
+            .filter(|s| tag.is_none_or(|tag| s.tags.iter().any(|t| t == tag)))
+            .collect())
+    }
+
+    // Returns every (key, scope, value) row of a profile decrypted
+    pub fn get_all_secrets(&mut self, profile: &str) -> Result<Vec<(String, String, SecretString)>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT key, scope, value FROM variables WHERE profile = ?1 ORDER BY key, scope",
+        )?;
+        let rows = stmt.query_map(params![profile], |row| {
             let key: String = row.get(0)?;
-            let encrypted_value: String = row.get(1)?;
-            Ok((key, encrypted_value))
+            let scope: String = row.get(1)?;
+            let encrypted_value: String = row.get(2)?;
+            Ok((key, scope, encrypted_value))
         })?;
 
         let mut secrets = Vec::new();
         for row in rows {
-            let (key, encrypted_value) = row?;
-            let decrypted = self.crypto.decrypt(&encrypted_value)?;
-            let value =
-                String::from_utf8(decrypted).map_err(|e| SvenError::ConfigError(e.to_string()))?;
-            secrets.push((key, value));
+            let (key, scope, encrypted_value) = row?;
+            let value = SecretString::new(self.crypto.decrypt(&encrypted_value)?);
+            secrets.push((key, scope, value));
         }
 
         Ok(secrets)
     }
+
+    // Returns a printable description of each recipient key
+    pub fn list_recipients(&mut self) -> Vec<String> {
+        let recipients = self.crypto.recipients().to_vec();
+        recipients
+            .iter()
+            .map(|id| self.crypto.describe_key(id))
+            .collect()
+    }
+
+    // Returns the key ID added and the secrets re-encrypted for it
+    pub fn add_recipient(&mut self, key_id: &str) -> Result<(String, Vec<SecretId>)> {
+        let key_id = self.crypto.find_recipient(key_id)?;
+        let mut recipients = self.crypto.recipients().to_vec();
+        if recipients.contains(&key_id) {
+            return Err(SvenError::ConfigError(format!(
+                "{} is already a recipient",
+                key_id
+            )));
+        }
+        recipients.push(key_id.clone());
+        let reencrypted = self.reencrypt_all(recipients, None)?;
+        Ok((key_id, reencrypted))
+    }
+
+    // Returns the secrets re-encrypted without the key
+    pub fn remove_recipient(&mut self, key_id: &str) -> Result<Vec<SecretId>> {
+        // Accepts a fingerprint like `add` does, or the ID of a key that has
+        // since left the keyring
+        let resolved = self.crypto.lookup_key_id(key_id).ok();
+        let mut recipients = self.crypto.recipients().to_vec();
+        let before = recipients.len();
+        recipients.retain(|id| !id.eq_ignore_ascii_case(key_id) && Some(id) != resolved.as_ref());
+        if recipients.len() == before {
+            return Err(SvenError::ConfigError(format!(
+                "{} is not a recipient",
+                key_id
+            )));
+        }
+
+        // Refuse to lock ourselves out of the store
+        if !recipients.iter().any(|id| self.crypto.has_secret_key(id)) {
+            return Err(SvenError::ConfigError(
+                "Cannot remove the last recipient with a secret key on this machine".into(),
+            ));
+        }
+        self.reencrypt_all(recipients, None)
+    }
+
+    // Replaces our own GPG key, `old_key` or else the one recipient whose
+    // secret key is on this machine, with `new_key` (or one picked
+    // interactively) and re-encrypts the whole store for it; returns the new
+    // key ID and the secrets re-encrypted
+    pub fn rekey(&mut self, old_key: Option<&str>, new_key: Option<&str>) -> Result<(String, Vec<SecretId>)> {
+        let old_key = self.own_recipient(old_key)?;
+        let new_key = match new_key {
+            Some(key_id) => self.crypto.find_recipient(key_id)?,
+            None => self.crypto.choose_key()?,
+        };
+        if !self.crypto.has_secret_key(&new_key) {
+            return Err(SvenError::ConfigError(format!(
+                "No secret key available for {}",
+                new_key
+            )));
+        }
+        if old_key == new_key {
+            return Err(SvenError::ConfigError(format!(
+                "{} is already your key",
+                new_key
+            )));
+        }
+
+        let mut recipients: Vec<String> = self
+            .crypto
+            .recipients()
+            .iter()
+            .filter(|id| **id != new_key)
+            .cloned()
+            .collect();
+        if let Some(slot) = recipients.iter_mut().find(|id| **id == old_key) {
+            *slot = new_key.clone();
+        }
+
+        // In a shared store the configured key may be a teammate's, which stays
+        let key_id = (self.crypto.key_id() == Some(old_key.as_str())).then(|| new_key.clone());
+        let reencrypted = self.reencrypt_all(recipients, key_id)?;
+        Ok((new_key, reencrypted))
+    }
+
+    // The recipient key belonging to whoever runs sven: `key_id` if given,
+    // else the only recipient with a secret key in the local keyring
+    fn own_recipient(&mut self, key_id: Option<&str>) -> Result<String> {
+        let recipients = self.crypto.recipients().to_vec();
+        if let Some(key_id) = key_id {
+            let resolved = self.crypto.lookup_key_id(key_id).ok();
+            return recipients
+                .into_iter()
+                .find(|id| id.eq_ignore_ascii_case(key_id) || Some(id) == resolved.as_ref())
+                .ok_or_else(|| SvenError::ConfigError(format!("{} is not a recipient", key_id)));
+        }
+
+        let mut own: Vec<String> = recipients
+            .into_iter()
+            .filter(|id| self.crypto.has_secret_key(id))
+            .collect();
+        match own.len() {
+            0 => Err(SvenError::ConfigError(
+                "None of the recipients has a secret key on this machine".into(),
+            )),
+            1 => Ok(own.remove(0)),
+            _ => Err(SvenError::ConfigError(format!(
+                "Several recipients have a secret key on this machine ({}); choose the one to replace with --from",
+                own.join(", ")
+            ))),
+        }
+    }
+
+    // Returns every (profile, key, scope, encrypted value) row
+    pub fn encrypted_rows(&self) -> Result<Vec<(String, String, String, String)>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT profile, key, scope, value FROM variables ORDER BY profile, key, scope",
+        )?;
+        let rows = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))?
+            .collect::<std::result::Result<Vec<(String, String, String, String)>, _>>()?;
+        Ok(rows)
+    }
+
+    pub fn decrypt_string(&mut self, encrypted_value: &str) -> Result<SecretString> {
+        Ok(SecretString::new(self.crypto.decrypt(encrypted_value)?))
+    }
+
+    // Changes whenever another connection commits to the database
+    pub fn data_version(&self) -> Result<i64> {
+        Ok(self.conn.query_row("PRAGMA data_version", [], |row| row.get(0))?)
+    }
+
+    // Identifies the database file, to notice when it is replaced (e.g. by a sync tool)
+    pub fn file_id() -> Result<(u64, u64)> {
+        let metadata = std::fs::metadata(Self::get_db_path()?)?;
+        Ok((metadata.dev(), metadata.ino()))
+    }
+
+    // Builds an armored, GPG-encrypted backup of every profile, secret and
+    // config row, encrypted to the recipients plus `extra` keys or to a
+    // passphrase; also returns the secrets written to it
+    pub fn backup(&mut self, extra: &[String], symmetric: bool) -> Result<(Vec<u8>, Vec<SecretId>)> {
+        let mut stmt = self.conn.prepare(
+            "SELECT profile, key, scope, description, tags, created_at, updated_at FROM variables",
+        )?;
+        let mut metadata = stmt
+            .query_map([], |row| {
+                let tags: String = row.get(4)?;
+                Ok((
+                    (row.get(0)?, row.get(1)?, row.get(2)?),
+                    (row.get(3)?, split_tags(&tags), row.get(5)?, row.get(6)?),
+                ))
+            })?
+            .collect::<std::result::Result<BTreeMap<(String, String, String), _>, _>>()?;
+        drop(stmt);
+
+        let mut variables = Vec::new();
+        for (profile, key, scope, encrypted_value) in self.encrypted_rows()? {
+            let value = self.decrypt_string(&encrypted_value)?;
+            let (description, tags, created_at, updated_at) = metadata
+                .remove(&(profile.clone(), key.clone(), scope.clone()))
+                .unwrap_or_default();
+            variables.push(BundleEntry {
+                profile,
+                key,
+                scope,
+                value,
+                description,
+                tags,
+                created_at,
+                updated_at,
+            });
+        }
+
+        let mut stmt = self.conn.prepare("SELECT key, value FROM config ORDER BY key")?;
+        let config = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<std::result::Result<Vec<(String, String)>, _>>()?;
+        drop(stmt);
+
+        let bundle = Bundle {
+            version: backup::BUNDLE_VERSION,
+            created_at: unix_now() as u64,
+            profiles: self.list_profiles()?,
+            config,
+            variables,
+        };
+
+        for key_id in extra {
+            self.crypto.find_recipient(key_id)?;
+        }
+        let ids = bundle
+            .variables
+            .iter()
+            .map(|e| (e.profile.clone(), e.key.clone(), e.scope.clone()))
+            .collect();
+        let armored = self.crypto.encrypt_armored(&Zeroizing::new(bundle.to_json()?), extra, symmetric)?;
+        Ok((armored, ids))
+    }
+
+    // Restores a backup made by `backup` in a single transaction
+    pub fn restore(&mut self, armored: &[u8], mode: RestoreMode) -> Result<RestoreSummary> {
+        let bundle = Bundle::from_json(&self.crypto.decrypt_armored(armored)?)?;
+        // Checked up front, like add_secret does, so a bad key doesn't leave the
+        // store half restored
+        for entry in &bundle.variables {
+            validate_key(&entry.key)?;
+        }
+        self.crypto.load_recipients(&self.conn)?;
+
+        let mut existing = BTreeMap::new();
+        for (profile, key, scope, encrypted_value) in self.encrypted_rows()? {
+            let value = self.decrypt_string(&encrypted_value)?;
+            existing.insert((profile, key, scope), value);
+        }
+
+        let mut summary = RestoreSummary::default();
+        let retention = self.history_retention()?;
+        let tx = self.conn.transaction()?;
+
+        if mode == RestoreMode::Overwrite {
+            let in_bundle: Vec<(&str, &str, &str)> = bundle
+                .variables
+                .iter()
+                .map(|e| (e.profile.as_str(), e.key.as_str(), e.scope.as_str()))
+                .collect();
+            for (profile, key, scope) in existing.keys() {
+                if !in_bundle.contains(&(profile.as_str(), key.as_str(), scope.as_str())) {
+                    record_history(&tx, profile, key, scope, retention)?;
+                    tx.execute(
+                        "DELETE FROM variables WHERE profile = ?1 AND key = ?2 AND scope = ?3",
+                        params![profile, key, scope],
+                    )?;
+                    summary.removed.push((profile.clone(), key.clone(), scope.clone()));
+                }
+            }
+        }
+
+        for profile in &bundle.profiles {
+            tx.execute(
+                "INSERT OR IGNORE INTO profiles (name) VALUES (?1)",
+                params![profile],
+            )?;
+        }
+
+        for (key, value) in &bundle.config {
+            if backup::MACHINE_CONFIG_KEYS.contains(&key.as_str()) {
+                continue;
+            }
+            let sql = if mode == RestoreMode::Overwrite {
+                "INSERT OR REPLACE INTO config (key, value) VALUES (?1, ?2)"
+            } else {
+                "INSERT OR IGNORE INTO config (key, value) VALUES (?1, ?2)"
+            };
+            tx.execute(sql, params![key, value])?;
+        }
+
+        for entry in bundle.variables {
+            let label = (entry.profile.clone(), entry.key.clone(), entry.scope.clone());
+            let current = existing.get(&label);
+            match current {
+                Some(value) if *value == entry.value => {
+                    summary.unchanged.push(label);
+                    continue;
+                }
+                Some(_) if mode == RestoreMode::SkipExisting => {
+                    summary.skipped.push(label);
+                    continue;
+                }
+                Some(_) => {
+                    record_history(&tx, &entry.profile, &entry.key, &entry.scope, retention)?;
+                    summary.updated.push(label);
+                }
+                None => summary.added.push(label),
+            }
+
+            let encrypted = self.crypto.encrypt(entry.value.expose())?;
+            tx.execute(
+                "INSERT OR REPLACE INTO variables
+                    (profile, key, scope, value, description, tags, created_at, updated_at)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                params![
+                    entry.profile,
+                    entry.key,
+                    entry.scope,
+                    encrypted,
+                    entry.description,
+                    join_tags(&entry.tags)?,
+                    entry.created_at,
+                    entry.updated_at
+                ],
+            )?;
+        }
+
+        tx.commit()?;
+        Ok(summary)
+    }
+
+    // Re-encrypts every stored value to `recipients` in a single transaction
+    // and saves the new list (and new own key, if given); nothing changes if
+    // any row fails. Returns the secrets re-encrypted.
+    fn reencrypt_all(&mut self, recipients: Vec<String>, key_id: Option<String>) -> Result<Vec<SecretId>> {
+        let rows = self.encrypted_rows()?;
+        let mut stmt = self.conn.prepare("SELECT id, value FROM variable_history")?;
+        let history = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<std::result::Result<Vec<(i64, String)>, _>>()?;
+        drop(stmt);
+
+        let previous = self.crypto.set_recipients(recipients);
+        let previous_key = key_id.map(|id| self.crypto.set_key_id(Some(id)));
+        let result = (|| {
+            let tx = self.conn.transaction()?;
+            let mut reencrypted = Vec::new();
+            for (profile, key, scope, encrypted_value) in rows {
+                let decrypted = self.crypto.decrypt(&encrypted_value)?;
+                let encrypted = self.crypto.encrypt(&decrypted)?;
+                tx.execute(
+                    "UPDATE variables SET value = ?1 WHERE profile = ?2 AND key = ?3 AND scope = ?4",
+                    params![encrypted, profile, key, scope],
+                )?;
+                reencrypted.push((profile, key, scope));
+            }
+            // Previous values must stay readable after a key change too
+            for (id, encrypted_value) in history {
+                let decrypted = self.crypto.decrypt(&encrypted_value)?;
+                let encrypted = self.crypto.encrypt(&decrypted)?;
+                tx.execute(
+                    "UPDATE variable_history SET value = ?1 WHERE id = ?2",
+                    params![encrypted, id],
+                )?;
+            }
+            self.crypto.save_recipients(&tx)?;
+            if previous_key.is_some() {
+                self.crypto.save_key_id(&tx)?;
+            }
+            tx.commit()?;
+            Ok(reencrypted)
+        })();
+
+        if result.is_err() {
+            self.crypto.set_recipients(previous);
+            if let Some(previous_key) = previous_key {
+                self.crypto.set_key_id(previous_key);
+            }
+        }
+        result
+    }
+
+    // Decrypts the value of a single key as seen from `dir`
+    pub fn get_secret(&mut self, profile: &str, key: &str, dir: &Path) -> Result<Option<SecretString>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT key, scope, value FROM variables WHERE profile = ?1 AND key = ?2")?;
+        // Keep the scope next to the value so the access can be recorded
+        let rows = stmt
+            .query_map(params![profile, key], |row| {
+                let scope: String = row.get(1)?;
+                Ok((row.get(0)?, scope.clone(), (scope, row.get::<_, String>(2)?)))
+            })?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        drop(stmt);
+
+        // Pick the visible row first so only one value gets decrypted
+        match resolve_scopes(rows, dir).pop() {
+            Some((key, (scope, encrypted_value))) => {
+                let value = self.decrypt_string(&encrypted_value)?;
+                self.touch_secrets(profile, &[(key, scope)])?;
+                Ok(Some(value))
+            }
+            None => Ok(None),
+        }
+    }
+
+    // Decrypts only the secrets of a profile that apply in `dir`
+    pub fn get_secrets_for_dir(&mut self, profile: &str, dir: &Path) -> Result<Vec<(String, SecretString)>> {
+        let rows = self
+            .get_all_secrets(profile)?
+            .into_iter()
+            .map(|(key, scope, value)| (key, scope.clone(), (scope, value)));
+        let resolved = resolve_scopes(rows, dir);
+
+        let touched: Vec<(String, String)> = resolved
+            .iter()
+            .map(|(key, (scope, _))| (key.clone(), scope.clone()))
+            .collect();
+        self.touch_secrets(profile, &touched)?;
+
+        Ok(resolved
+            .into_iter()
+            .map(|(key, (_, value))| (key, value))
+            .collect())
+    }
+}
+
+// Copies the current value of a secret, if any, into variable_history and
+// drops versions beyond `retention`
+fn record_history(conn: &Connection, profile: &str, key: &str, scope: &str, retention: u32) -> Result<()> {
+    if retention == 0 {
+        return Ok(());
+    }
+    conn.execute(
+        "INSERT INTO variable_history (profile, key, scope, value, replaced_at)
+            SELECT profile, key, scope, value, ?4 FROM variables
+            WHERE profile = ?1 AND key = ?2 AND scope = ?3",
+        params![profile, key, scope, unix_now()],
+    )?;
+    conn.execute(
+        "DELETE FROM variable_history
+         WHERE profile = ?1 AND key = ?2 AND scope = ?3 AND id NOT IN (
+            SELECT id FROM variable_history
+            WHERE profile = ?1 AND key = ?2 AND scope = ?3
+            ORDER BY id DESC LIMIT ?4
+         )",
+        params![profile, key, scope, retention],
+    )?;
+    Ok(())
+}
+
+pub fn unix_now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+fn split_tags(tags: &str) -> Vec<String> {
+    tags.split(',')
+        .filter(|t| !t.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn join_tags(tags: &[String]) -> Result<String> {
+    if let Some(tag) = tags.iter().find(|t| t.is_empty() || t.contains(',')) {
+        return Err(SvenError::ConfigError(format!(
+            "Invalid tag '{}': tags must be non-empty and cannot contain ','",
+            tag
+        )));
+    }
+    Ok(tags.join(","))
+}
+
+fn validate_profile_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+    if valid {
+        Ok(())
+    } else {
+        Err(SvenError::ConfigError(format!(
+            "Invalid profile name '{}': use letters, digits, '-', '_' or '.'",
+            name
+        )))
+    }
+}
+
+// Keys become shell variable names that exports interpolate unquoted, so they
+// must follow the POSIX name grammar: [A-Za-z_][A-Za-z0-9_]*
+pub fn validate_key(key: &str) -> Result<()> {
+    let mut chars = key.chars();
+    let valid = chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        return Err(SvenError::InvalidKey(key.to_string()));
+    }
+    Ok(())
+}
+
+// Turns a scope argument into the canonical path prefix stored in the database
+pub fn normalize_scope(scope: Option<&str>) -> Result<String> {
+    match scope {
+        None => Ok(GLOBAL_SCOPE.to_string()),
+        Some(path) => {
+            let canonical = std::fs::canonicalize(path).map_err(|e| {
+                SvenError::ConfigError(format!("Invalid scope '{}': {}", path, e))
+            })?;
+            Ok(canonical.to_string_lossy().into_owned())
+        }
+    }
+}
+
+// Picks the secrets visible from `dir`: global ones plus every scope that is a
+// prefix of `dir`, with the deepest matching scope winning for each key
+pub fn resolve_scopes<I, V>(rows: I, dir: &Path) -> Vec<(String, V)>
+where
+    I: IntoIterator<Item = (String, String, V)>,
+{
+    let mut selected: BTreeMap<String, (usize, V)> = BTreeMap::new();
+    for (key, scope, value) in rows {
+        let depth = if scope == GLOBAL_SCOPE {
+            0
+        } else if dir.starts_with(&scope) {
+            Path::new(&scope).components().count()
+        } else {
+            continue;
+        };
+
+        match selected.get(&key) {
+            Some((existing, _)) if *existing >= depth => {}
+            _ => {
+                selected.insert(key, (depth, value));
+            }
+        }
+    }
+
+    selected
+        .into_iter()
+        .map(|(key, (_, value))| (key, value))
+        .collect()
 }
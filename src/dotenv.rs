@@ -0,0 +1,238 @@
+use crate::error::{Result, SvenError};
+use crate::secret::{self, SecretString};
+use zeroize::Zeroizing;
+
+// Parses a .env file into (key, value) pairs in file order. Supports
+// `export ` prefixes, `#` comments, single quotes (literal), double quotes
+// (with \n, \r, \t, \", \\ and \$ escapes) and quoted values spanning lines.
+pub fn parse(input: &str) -> Result<Vec<(String, SecretString)>> {
+    let mut entries = Vec::new();
+    let mut chars = input.chars().peekable();
+    let mut line = 1;
+
+    loop {
+        // Skip blank lines, indentation and comment lines
+        while let Some(&c) = chars.peek() {
+            if c == '\n' {
+                line += 1;
+                chars.next();
+            } else if c.is_whitespace() {
+                chars.next();
+            } else if c == '#' {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        line += 1;
+                        break;
+                    }
+                }
+            } else {
+                break;
+            }
+        }
+        if chars.peek().is_none() {
+            break;
+        }
+
+        let start_line = line;
+        let mut key = String::new();
+        while let Some(&c) = chars.peek() {
+            if c == '=' || c == '\n' {
+                break;
+            }
+            key.push(c);
+            chars.next();
+        }
+        if chars.next() != Some('=') {
+            return Err(parse_error(start_line, "expected KEY=VALUE"));
+        }
+
+        let key = key.trim();
+        let key = key
+            .strip_prefix("export")
+            .filter(|rest| rest.starts_with(char::is_whitespace))
+            .map(str::trim_start)
+            .unwrap_or(key);
+        if key.is_empty() {
+            return Err(parse_error(start_line, "missing key"));
+        }
+
+        while chars.peek().is_some_and(|c| *c == ' ' || *c == '\t') {
+            chars.next();
+        }
+
+        let value = match chars.peek() {
+            Some('"') => {
+                chars.next();
+                let mut value = Zeroizing::new(Vec::new());
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => push(&mut value, '\n'),
+                            Some('r') => push(&mut value, '\r'),
+                            Some('t') => push(&mut value, '\t'),
+                            Some(c @ ('"' | '\\' | '$')) => push(&mut value, c),
+                            Some(c) => {
+                                if c == '\n' {
+                                    line += 1;
+                                }
+                                push(&mut value, '\\');
+                                push(&mut value, c);
+                            }
+                            None => return Err(parse_error(start_line, "unterminated double quote")),
+                        },
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            push(&mut value, c);
+                        }
+                        None => return Err(parse_error(start_line, "unterminated double quote")),
+                    }
+                }
+                skip_rest_of_line(&mut chars, start_line)?;
+                value
+            }
+            Some('\'') => {
+                chars.next();
+                let mut value = Zeroizing::new(Vec::new());
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            push(&mut value, c);
+                        }
+                        None => return Err(parse_error(start_line, "unterminated single quote")),
+                    }
+                }
+                skip_rest_of_line(&mut chars, start_line)?;
+                value
+            }
+            _ => {
+                // Unquoted values end at the line end or at a comment: a '#'
+                // at the start of the value or after whitespace
+                let mut value = Zeroizing::new(Vec::new());
+                // Length without trailing whitespace
+                let mut end = 0;
+                let mut after_whitespace = true;
+                while let Some(&c) = chars.peek() {
+                    if c == '\n' || (c == '#' && after_whitespace) {
+                        break;
+                    }
+                    push(&mut value, c);
+                    if !c.is_whitespace() {
+                        end = value.len();
+                    }
+                    after_whitespace = c.is_whitespace();
+                    chars.next();
+                }
+                value.truncate(end);
+                value
+            }
+        };
+
+        entries.push((key.to_string(), SecretString::new(value)));
+    }
+
+    Ok(entries)
+}
+
+// Values are built in place so that growing them leaves no copies behind
+fn push(value: &mut Zeroizing<Vec<u8>>, c: char) {
+    secret::extend_zeroized(value, c.encode_utf8(&mut [0; 4]).as_bytes());
+}
+
+// After a closing quote only whitespace or a comment may follow
+fn skip_rest_of_line(chars: &mut std::iter::Peekable<std::str::Chars>, line: usize) -> Result<()> {
+    let mut in_comment = false;
+    while let Some(&c) = chars.peek() {
+        if c == '\n' {
+            break;
+        }
+        if c == '#' {
+            in_comment = true;
+        } else if !in_comment && !c.is_whitespace() {
+            return Err(parse_error(line, "unexpected characters after closing quote"));
+        }
+        chars.next();
+    }
+    Ok(())
+}
+
+fn parse_error(line: usize, message: &str) -> SvenError {
+    SvenError::ConfigError(format!("Invalid .env file at line {}: {}", line, message))
+}
+
+// Formats one entry so that `parse` reads back exactly the same value
+pub fn format_entry(key: &str, value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '$' => escaped.push_str("\\$"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c => escaped.push(c),
+        }
+    }
+    format!("{}=\"{}\"", key, escaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn entries(pairs: &[(&str, &str)]) -> Vec<(String, SecretString)> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string().into())).collect()
+    }
+
+    #[test]
+    fn unquoted_values() {
+        let input = "A=plain\nexport B = spaced value  \n  C=x#y\nD=\n";
+        assert_eq!(
+            parse(input).unwrap(),
+            entries(&[("A", "plain"), ("B", "spaced value"), ("C", "x#y"), ("D", "")])
+        );
+    }
+
+    #[test]
+    fn quoted_values() {
+        let input = "A=\"line\\none \\\"q\\\" \\$HOME \\\\\"\nB='lit \\n $X'\nC=\"multi\nline\"\n";
+        assert_eq!(
+            parse(input).unwrap(),
+            entries(&[("A", "line\none \"q\" $HOME \\"), ("B", "lit \\n $X"), ("C", "multi\nline")])
+        );
+    }
+
+    #[test]
+    fn commented_values() {
+        let input = "# heading\nA=value # comment\nB= # comment\nC=#comment\nD=\"quoted # kept\" # comment\nE='x' #c\n";
+        assert_eq!(
+            parse(input).unwrap(),
+            entries(&[("A", "value"), ("B", ""), ("C", ""), ("D", "quoted # kept"), ("E", "x")])
+        );
+    }
+
+    #[test]
+    fn errors_report_the_line() {
+        let err = parse("A=1\n\nB=\"open\nstill open").unwrap_err().to_string();
+        assert!(err.contains("line 3"), "{}", err);
+        assert!(parse("just words\n").is_err());
+        assert!(parse("=value\n").is_err());
+        assert!(parse("A='quoted'trailing\n").is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn format_entry_round_trips(key in "[A-Za-z_][A-Za-z0-9_]{0,16}", value in any::<String>()) {
+            let parsed = parse(&format_entry(&key, &value)).unwrap();
+            prop_assert_eq!(parsed, vec![(key, value.into())]);
+        }
+    }
+}
@@ -29,6 +29,12 @@ pub enum SvenError {
     
     #[error("Channel send error: {0}")]
     ChannelSendError(String),
+
+    #[error("Daemon protocol mismatch: {0}")]
+    ProtocolMismatch(String),
+
+    #[error("Invalid key name {0:?}: must start with a letter or underscore and contain only letters, digits and underscores")]
+    InvalidKey(String),
 }
 
 impl<T> From<SendError<T>> for SvenError {
@@ -0,0 +1,112 @@
+use crate::db;
+use crate::error::{Result, SvenError};
+use log::{LevelFilter, Log, Metadata, Record};
+use std::io::Write;
+use std::os::unix::fs::DirBuilderExt;
+use std::path::PathBuf;
+
+// Environment variables that configure the daemon log
+const LEVEL_VAR: &str = "SVEN_LOG";
+const FORMAT_VAR: &str = "SVEN_LOG_FORMAT";
+
+// $XDG_STATE_HOME/sven (or the local data directory), private to the user
+pub fn state_dir() -> Result<PathBuf> {
+    let dir = dirs::state_dir()
+        .or_else(dirs::data_local_dir)
+        .map(|mut p| {
+            p.push("sven");
+            p
+        })
+        .ok_or_else(|| SvenError::ConfigError("Could not determine state directory".into()))?;
+    std::fs::DirBuilder::new().recursive(true).mode(0o700).create(&dir)?;
+    Ok(dir)
+}
+
+// The daemon's stdout and stderr are redirected here
+pub fn daemon_log_path() -> Result<PathBuf> {
+    Ok(state_dir()?.join("daemon.log"))
+}
+
+// Writes log lines to stderr, as text or one JSON object per line
+struct StderrLogger {
+    level: LevelFilter,
+    json: bool,
+}
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let now = db::unix_now();
+        let line = if self.json {
+            serde_json::json!({
+                "time": now,
+                "level": record.level().as_str().to_lowercase(),
+                "message": record.args().to_string(),
+            })
+            .to_string()
+        } else {
+            format!("{} {:<5} {}", format_timestamp(now), record.level(), record.args())
+        };
+        let _ = writeln!(std::io::stderr().lock(), "{}", line);
+    }
+
+    fn flush(&self) {
+        let _ = std::io::stderr().flush();
+    }
+}
+
+// Installs the logger, configured by SVEN_LOG (error, warn, info, debug or
+// trace; info by default) and SVEN_LOG_FORMAT (text or json)
+pub fn init() -> Result<()> {
+    let level = match std::env::var(LEVEL_VAR) {
+        Ok(value) => value
+            .parse::<LevelFilter>()
+            .map_err(|_| SvenError::ConfigError(format!("Invalid {}: {}", LEVEL_VAR, value)))?,
+        Err(_) => LevelFilter::Info,
+    };
+    let json = match std::env::var(FORMAT_VAR).as_deref() {
+        Ok("json") => true,
+        Ok("text") | Err(_) => false,
+        Ok(value) => {
+            return Err(SvenError::ConfigError(format!(
+                "Invalid {}: {} (expected text or json)",
+                FORMAT_VAR, value
+            )))
+        }
+    };
+    log::set_boxed_logger(Box::new(StderrLogger { level, json }))
+        .map_err(|e| SvenError::ConfigError(format!("Failed to set up logging: {}", e)))?;
+    log::set_max_level(level);
+    Ok(())
+}
+
+// Formats seconds since the epoch as "YYYY-MM-DD HH:MM:SS" UTC, the same
+// shape SQLite's datetime() gives the other timestamps sven prints
+pub fn format_timestamp(secs: i64) -> String {
+    let (days, rem) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
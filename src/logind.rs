@@ -0,0 +1,44 @@
+use crate::error::{Result, SvenError};
+use futures_util::StreamExt;
+use zbus::zvariant::OwnedObjectPath;
+use zbus::{Connection, Proxy};
+
+const DESTINATION: &str = "org.freedesktop.login1";
+
+fn bus_error(e: zbus::Error) -> SvenError {
+    SvenError::ConfigError(format!("logind: {}", e))
+}
+
+// Calls `on_lock` each time the login session the daemon runs in is locked,
+// either through logind's Lock signal (`loginctl lock-session`) or by a screen
+// locker setting the session's LockedHint. Only returns on error.
+pub async fn watch_session_lock(mut on_lock: impl FnMut()) -> Result<()> {
+    let connection = Connection::system().await.map_err(bus_error)?;
+    let manager = Proxy::new(
+        &connection,
+        DESTINATION,
+        "/org/freedesktop/login1",
+        "org.freedesktop.login1.Manager",
+    )
+    .await
+    .map_err(bus_error)?;
+    // "auto" is the caller's session, or else the user's graphical session
+    let path: OwnedObjectPath = manager.call("GetSession", &("auto",)).await.map_err(bus_error)?;
+    let session = Proxy::new(&connection, DESTINATION, path, "org.freedesktop.login1.Session")
+        .await
+        .map_err(bus_error)?;
+
+    let mut locks = session.receive_signal("Lock").await.map_err(bus_error)?;
+    let mut hints = session.receive_property_changed::<bool>("LockedHint").await;
+    loop {
+        tokio::select! {
+            Some(_) = locks.next() => on_lock(),
+            Some(change) = hints.next() => {
+                if change.get().await.map_err(bus_error)? {
+                    on_lock();
+                }
+            },
+            else => return Err(SvenError::ConfigError("logind: connection closed".into())),
+        }
+    }
+}
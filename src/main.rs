@@ -1,12 +1,28 @@
+mod audit;
+mod backup;
+mod clipboard;
 mod crypto;
 mod daemon;
 mod db;
+mod dotenv;
 mod error;
+mod logging;
+#[cfg(target_os = "linux")]
+mod logind;
+mod migrations;
+mod protocol;
+mod secret;
+mod shell;
 
 use anyhow::Result;
+use audit::{Actor, AuditAction, AuditLog};
 use clap::{Parser, Subcommand};
-use daemon::{Daemon, DaemonClient};
+use daemon::{Daemon, DaemonClient, LockTimeouts};
 use db::Database;
+use secret::SecretString;
+use shell::ExportShell;
+use std::collections::BTreeMap;
+use zeroize::Zeroizing;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -19,21 +35,244 @@ struct Cli {
 enum Commands {
     Add {
         key: String,
-        value: String,
+        /// Secret value; prompted for with hidden input when omitted
+        value: Option<String>,
+        /// Read the value from standard input
+        #[arg(long, conflicts_with_all = ["value", "from_file"])]
+        stdin: bool,
+        /// Read the value from a file
+        #[arg(long, value_name = "PATH", conflicts_with = "value")]
+        from_file: Option<std::path::PathBuf>,
+        /// Only export this secret inside the given directory
+        #[arg(long)]
+        scope: Option<String>,
+        /// Profile to store the secret in (defaults to the active profile)
+        #[arg(short, long)]
+        profile: Option<String>,
+        /// What the secret is for
+        #[arg(short, long)]
+        description: Option<String>,
+        /// Tag to attach (repeatable); replaces existing tags when given
+        #[arg(short, long = "tag")]
+        tags: Vec<String>,
     },
     Remove {
         key: String,
+        /// Remove the secret stored for this directory instead of the global one
+        #[arg(long)]
+        scope: Option<String>,
+        /// Profile to remove the secret from (defaults to the active profile)
+        #[arg(short, long)]
+        profile: Option<String>,
+    },
+    /// Print a single decrypted secret
+    Get {
+        key: String,
+        /// Print the value without a trailing newline
+        #[arg(long)]
+        raw: bool,
+        /// Copy the value to the clipboard instead of printing it
+        #[arg(long, conflicts_with = "raw")]
+        clip: bool,
+        /// Seconds until the clipboard is cleared, 0 to keep it
+        #[arg(long, default_value_t = 45, requires = "clip")]
+        clear_after: u64,
+        /// Profile to read from (defaults to the active profile)
+        #[arg(short, long)]
+        profile: Option<String>,
+    },
+    List {
+        /// Profile to list (defaults to the active profile)
+        #[arg(short, long)]
+        profile: Option<String>,
+        /// Show description, tags and timestamps
+        #[arg(short, long)]
+        long: bool,
+        /// Only list secrets with this tag
+        #[arg(short, long)]
+        tag: Option<String>,
     },
-    List,
     Export {
-        #[arg(short, long, default_value = "fish")]
-        shell: String,
+        #[arg(short, long, value_enum, default_value = "fish")]
+        shell: shell::ExportShell,
+        /// Output format; `shell` uses the syntax of --shell
+        #[arg(short, long, value_enum, default_value = "shell")]
+        format: ExportFormat,
+        /// Profile to export (defaults to the active profile)
+        #[arg(short, long)]
+        profile: Option<String>,
+    },
+    /// Import secrets from a file such as a .env
+    Import {
+        file: std::path::PathBuf,
+        #[arg(short, long, value_enum, default_value = "dotenv")]
+        format: ImportFormat,
+        /// Only export the imported secrets inside the given directory
+        #[arg(long)]
+        scope: Option<String>,
+        /// Profile to import into (defaults to the active profile)
+        #[arg(short, long)]
+        profile: Option<String>,
+    },
+    /// Run a command with secrets injected into its environment
+    Run {
+        /// Only inject these keys (comma separated)
+        #[arg(long, value_delimiter = ',')]
+        only: Vec<String>,
+        /// Profile to load (defaults to the active profile)
+        #[arg(short, long)]
+        profile: Option<String>,
+        #[arg(last = true, required = true)]
+        command: Vec<String>,
+    },
+    /// Show when previous values of a secret were replaced, or set how many are kept
+    History {
+        #[arg(required_unless_present = "retention")]
+        key: Option<String>,
+        /// Show the history of the secret stored for this directory
+        #[arg(long)]
+        scope: Option<String>,
+        /// Profile the secret is in (defaults to the active profile)
+        #[arg(short, long)]
+        profile: Option<String>,
+        /// Number of previous values to keep per secret (0 disables history)
+        #[arg(long)]
+        retention: Option<u32>,
+    },
+    /// Restore a previous value of a secret
+    Rollback {
+        key: String,
+        /// Version to restore as listed by `sven history`; 1 is the latest replaced value
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+        version: u32,
+        /// Roll back the secret stored for this directory
+        #[arg(long)]
+        scope: Option<String>,
+        /// Profile the secret is in (defaults to the active profile)
+        #[arg(short, long)]
+        profile: Option<String>,
+    },
+    /// Manage named profiles such as dev, staging or prod
+    Profile {
+        #[command(subcommand)]
+        command: ProfileCommands,
+    },
+    /// Manage the GPG keys every secret is encrypted to
+    Recipients {
+        #[command(subcommand)]
+        command: RecipientCommands,
+    },
+    /// Write an encrypted backup of all secrets, profiles and settings
+    Backup {
+        file: std::path::PathBuf,
+        /// Also encrypt the backup to this key (repeatable), e.g. a new machine's key
+        #[arg(long)]
+        recipient: Vec<String>,
+        /// Encrypt the backup with a passphrase instead of GPG keys
+        #[arg(long, conflicts_with = "recipient")]
+        symmetric: bool,
+    },
+    /// Restore secrets from a backup made with `sven backup`
+    Restore {
+        file: std::path::PathBuf,
+        #[arg(long, value_enum, default_value = "merge")]
+        mode: backup::RestoreMode,
+    },
+    /// Switch to a new GPG key and re-encrypt every secret for it
+    Rekey {
+        /// Key ID to switch to; prompts for one when omitted
+        #[arg(long)]
+        to: Option<String>,
+        /// Recipient key ID or fingerprint to replace; defaults to the one whose secret key is on this machine
+        #[arg(long)]
+        from: Option<String>,
+    },
+    /// Start the daemon and cache decrypted secrets in memory
+    Unlock {
+        /// Lock the cache after this long without use, e.g. 30m or 1h30m
+        #[arg(long, value_parser = daemon::parse_duration)]
+        timeout: Option<std::time::Duration>,
+        /// Lock the cache this long after unlocking, even while in use
+        #[arg(long, value_parser = daemon::parse_duration)]
+        max_lifetime: Option<std::time::Duration>,
+    },
+    /// Print the changes to exported secrets since the last run, for shell prompt hooks
+    Hook {
+        #[arg(short, long, value_enum)]
+        shell: shell::ExportShell,
+        /// Profile to export (defaults to the active profile)
+        #[arg(short, long)]
+        profile: Option<String>,
+    },
+    /// Print the shell integration script, e.g. `eval "$(sven init bash)"`
+    Init {
+        #[arg(value_enum)]
+        shell: shell::InitShell,
+    },
+    /// Load the shell integration from your shell's rc file
+    InstallHook {
+        /// Shell to install for (detected from $SHELL by default)
+        #[arg(short, long, value_enum)]
+        shell: Option<shell::InitShell>,
+        /// Edit this file instead of the shell's usual rc file
+        #[arg(long)]
+        rc_file: Option<std::path::PathBuf>,
+    },
+    /// Show who read or changed secrets, from the audit log
+    Audit {
+        /// Only show entries for this key
+        #[arg(long)]
+        key: Option<String>,
+        /// Only show entries from this long ago onwards, e.g. 1h or 7d
+        #[arg(long, value_parser = daemon::parse_duration)]
+        since: Option<std::time::Duration>,
+    },
+    /// Check stored secrets for problems, such as keys that aren't valid variable names
+    Doctor,
+    /// Clear the clipboard after a delay if it still holds a copied secret
+    #[command(hide = true)]
+    ClearClipboard {
+        #[arg(long)]
+        after: u64,
     },
-    Unlock,
+    /// Drop the daemon's cached secrets without stopping it
+    Lock,
+    /// Make the daemon pick up changes made to the database outside it
+    Reload,
     Status,
     Stop,
 }
 
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq, Eq)]
+enum ExportFormat {
+    Shell,
+    Dotenv,
+    Json,
+    Yaml,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy)]
+enum ImportFormat {
+    Dotenv,
+}
+
+#[derive(Subcommand)]
+enum ProfileCommands {
+    Create { name: String },
+    List,
+    Use { name: String },
+    Delete { name: String },
+}
+
+#[derive(Subcommand)]
+enum RecipientCommands {
+    /// Add a key and re-encrypt every secret to it
+    Add { key_id: String },
+    /// Remove a key and re-encrypt every secret without it
+    Remove { key_id: String },
+    List,
+}
+
 fn print_line(line: &str) -> std::io::Result<()> {
     use std::io::Write;
     let stdout = std::io::stdout();
@@ -41,45 +280,384 @@ fn print_line(line: &str) -> std::io::Result<()> {
     writeln!(handle, "{}", line)
 }
 
-fn escape_value_for_shell(value: &str, shell: &str) -> String {
-    match shell {
-        "fish" => {
-            // Fish escapes $ with \$
-            value.replace('$', "\\$")
-        }
-        "bash" | "sh" | "zsh" => {
-            // Bash/sh/zsh escapes $ with \$, ` with \`, " with \", and \ with \\
-            value
-                .replace('\\', "\\\\")
-                .replace('$', "\\$")
-                .replace('`', "\\`")
-                .replace('"', "\\\"")
-        }
-        "csh" | "tcsh" => {
-            // csh/tcsh escapes $ with \$, ! with \!, and " with \"
-            value
-                .replace('$', "\\$")
-                .replace('!', "\\!")
-                .replace('"', "\\\"")
+// Environment variable holding the keys set by the previous export, so keys
+// that no longer apply (e.g. after leaving a scoped directory) can be unset
+const EXPORTED_KEYS_VAR: &str = "SVEN_KEYS";
+
+fn current_dir_string() -> Result<String> {
+    let dir = std::env::current_dir()?;
+    let dir = std::fs::canonicalize(&dir).unwrap_or(dir);
+    Ok(dir.to_string_lossy().into_owned())
+}
+
+fn format_key_entry(key: &str, scope: &str) -> String {
+    if scope == db::GLOBAL_SCOPE {
+        format!("  {}", key)
+    } else {
+        format!("  {} (scope: {})", key, scope)
+    }
+}
+
+fn print_secret_list(secrets: &[db::SecretInfo], long: bool) {
+    if secrets.is_empty() {
+        if print_line("No secrets found").is_err() {
+            std::process::exit(0);
         }
-        _ => {
-            // Default to bash-style escaping
-            value
-                .replace('\\', "\\\\")
-                .replace('$', "\\$")
-                .replace('`', "\\`")
-                .replace('"', "\\\"")
+        return;
+    }
+
+    let mut lines = vec!["Secrets:".to_string()];
+    for secret in secrets {
+        lines.push(format_key_entry(&secret.key, &secret.scope));
+        if !long {
+            continue;
+        }
+        let never = "-".to_string();
+        if let Some(description) = &secret.description {
+            lines.push(format!("      description:   {}", description));
+        }
+        if !secret.tags.is_empty() {
+            lines.push(format!("      tags:          {}", secret.tags.join(", ")));
+        }
+        lines.push(format!("      created:       {}", secret.created_at.as_ref().unwrap_or(&never)));
+        lines.push(format!("      updated:       {}", secret.updated_at.as_ref().unwrap_or(&never)));
+        lines.push(format!("      last accessed: {}", secret.last_accessed.as_ref().unwrap_or(&never)));
+    }
+
+    for line in lines {
+        if print_line(&line).is_err() {
+            std::process::exit(0);
+        }
+    }
+}
+
+fn secret_meta(description: Option<String>, tags: Vec<String>) -> db::SecretMeta {
+    db::SecretMeta {
+        description,
+        tags: if tags.is_empty() { None } else { Some(tags) },
+    }
+}
+
+// Resolves the value for `sven add` from the argument, stdin, a file, or a
+// hidden prompt, so tokens don't have to appear in shell history or `ps`
+fn read_secret_value(
+    key: &str,
+    value: Option<String>,
+    stdin: bool,
+    from_file: Option<&std::path::Path>,
+) -> error::Result<SecretString> {
+    use std::io::Read;
+
+    if let Some(value) = value {
+        return Ok(value.into());
+    }
+
+    let bytes = if stdin {
+        let mut buf = Zeroizing::new(Vec::new());
+        std::io::stdin().read_to_end(&mut buf)?;
+        buf
+    } else if let Some(path) = from_file {
+        Zeroizing::new(std::fs::read(path)?)
+    } else {
+        return dialoguer::Password::new()
+            .with_prompt(format!("Value for {}", key))
+            .interact()
+            .map(SecretString::from)
+            .map_err(|e| error::SvenError::ConfigError(e.to_string()));
+    };
+
+    Ok(SecretString::new(bytes))
+}
+
+fn output_secret(key: &str, value: Option<SecretString>, raw: bool, clip: bool, clear_after: u64) {
+    let value = match value {
+        Some(value) => value,
+        None => {
+            eprintln!("Secret not found: {}", key);
+            std::process::exit(1);
+        }
+    };
+
+    if clip {
+        match value.as_str().and_then(|text| clipboard::copy_with_clear(text, clear_after)) {
+            Ok(()) if clear_after > 0 => eprintln!(
+                "Copied {} to clipboard. Will clear in {} seconds.",
+                key, clear_after
+            ),
+            Ok(()) => eprintln!("Copied {} to clipboard.", key),
+            Err(e) => {
+                eprintln!("Failed to copy secret: {}", e);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        use std::io::Write;
+        let mut stdout = std::io::stdout().lock();
+        let newline: &[u8] = if raw { b"" } else { b"\n" };
+        if stdout
+            .write_all(value.expose())
+            .and_then(|_| stdout.write_all(newline))
+            .and_then(|_| stdout.flush())
+            .is_err()
+        {
+            std::process::exit(0);
         }
     }
 }
 
-fn format_export(key: &str, value: &str, shell: &str) -> String {
-    let escaped_value = escape_value_for_shell(value, shell);
-    match shell {
-        "fish" => format!("set -gx {} \"{}\"", key, escaped_value),
-        "bash" | "sh" | "zsh" => format!("export {}=\"{}\"", key, escaped_value),
-        "csh" | "tcsh" => format!("setenv {} \"{}\"", key, escaped_value),
-        _ => format!("export {}=\"{}\"", key, escaped_value), // default to bash-style
+fn print_profiles(names: &[String], active: &str) {
+    for name in names {
+        let marker = if name == active { "*" } else { " " };
+        if print_line(&format!("{} {}", marker, name)).is_err() {
+            std::process::exit(0);
+        }
+    }
+}
+
+// Replaces the current process with `command`, so the exit code and signals
+// belong to the child and nothing is written back to the calling shell
+fn run_with_secrets(secrets: Vec<(String, SecretString)>, only: &[String], command: &[String]) -> Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::process::CommandExt;
+
+    if let Some(missing) = only.iter().find(|k| !secrets.iter().any(|(key, _)| key == *k)) {
+        anyhow::bail!("Secret not found: {}", missing);
+    }
+
+    let mut child = std::process::Command::new(&command[0]);
+    child.args(&command[1..]);
+    for (key, value) in secrets {
+        if (only.is_empty() || only.contains(&key)) && valid_key(&key) {
+            child.env(key, std::ffi::OsStr::from_bytes(value.expose()));
+        }
+    }
+
+    let err = child.exec();
+    anyhow::bail!("Failed to run {}: {}", command[0], err)
+}
+
+// Records secrets read or changed without the daemon, which audits its own clients
+fn audit(action: AuditAction, profile: &str, keys: &[&str], scope: Option<&str>) {
+    let result = AuditLog::open().and_then(|log| {
+        let actor = Actor::current();
+        keys.iter().try_for_each(|key| log.record(&actor, action, profile, key, scope))
+    });
+    if let Err(e) = result {
+        eprintln!("Failed to write audit log: {}", e);
+    }
+}
+
+// Like `audit`, for secrets spread over several profiles
+fn audit_ids(action: AuditAction, ids: &[backup::SecretId]) {
+    for (profile, key, scope) in ids {
+        audit(action, profile, &[key], Some(scope));
+    }
+}
+
+fn read_import_file(path: &std::path::Path, format: ImportFormat) -> error::Result<Vec<(String, SecretString)>> {
+    use std::io::Read;
+
+    // Sized up front so reading doesn't reallocate and leave copies behind
+    let mut file = std::fs::File::open(path)?;
+    let mut contents = Zeroizing::new(String::with_capacity(file.metadata()?.len() as usize + 1));
+    file.read_to_string(&mut contents)?;
+    let entries = match format {
+        ImportFormat::Dotenv => dotenv::parse(&contents)?,
+    };
+    // Checked up front so a bad key doesn't leave the file half imported
+    for (key, _) in &entries {
+        db::validate_key(key)?;
+    }
+    Ok(entries)
+}
+
+// Keys are quoted too, since YAML 1.1 parsers read plain keys such as NO,
+// ON or NULL as booleans or null
+fn format_yaml_entry(key: &str, value: &str) -> String {
+    // JSON strings are valid YAML double-quoted scalars
+    format!(
+        "{}: {}",
+        serde_json::to_string(key).unwrap_or_default(),
+        serde_json::to_string(value).unwrap_or_default()
+    )
+}
+
+// Whether `key` can be a variable name, warning when it can't. Older versions
+// accepted any key, so the store may still hold some; `sven doctor` lists them.
+fn valid_key(key: &str) -> bool {
+    match db::validate_key(key) {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!("Skipping {}: {}", key, e);
+            false
+        }
+    }
+}
+
+// The secrets with valid keys and text values, warning about the rest, for
+// formats that can't carry arbitrary bytes
+fn text_secrets(secrets: &[(String, SecretString)]) -> Vec<(&str, &str)> {
+    secrets
+        .iter()
+        .filter(|(key, _)| valid_key(key))
+        .filter_map(|(key, value)| match value.as_str() {
+            Ok(text) => Some((key.as_str(), text)),
+            Err(e) => {
+                eprintln!("Skipping {}: {}", key, e);
+                None
+            }
+        })
+        .collect()
+}
+
+fn print_secrets(secrets: &[(String, SecretString)], format: ExportFormat, shell: ExportShell) {
+    let lines: Vec<Zeroizing<String>> = match format {
+        ExportFormat::Shell => return print_exports(secrets, shell),
+        ExportFormat::Dotenv => text_secrets(secrets)
+            .into_iter()
+            .map(|(key, value)| Zeroizing::new(dotenv::format_entry(key, value)))
+            .collect(),
+        ExportFormat::Json => {
+            let object: BTreeMap<&str, &str> = text_secrets(secrets).into_iter().collect();
+            vec![Zeroizing::new(serde_json::to_string_pretty(&object).unwrap_or_default())]
+        }
+        ExportFormat::Yaml => text_secrets(secrets)
+            .into_iter()
+            .map(|(key, value)| Zeroizing::new(format_yaml_entry(key, value)))
+            .collect(),
+    };
+
+    for line in lines {
+        if print_line(&line).is_err() {
+            std::process::exit(0);
+        }
+    }
+}
+
+// The export statement for one variable, or None with a warning when the
+// shell can't hold its value, so one odd secret doesn't block the rest
+fn export_line(shell: ExportShell, key: &str, value: &[u8]) -> Option<Zeroizing<String>> {
+    match shell.export(key, value) {
+        Ok(line) => Some(Zeroizing::new(line)),
+        Err(e) => {
+            eprintln!("Skipping {}: {}", key, e);
+            None
+        }
+    }
+}
+
+fn print_exports(secrets: &[(String, SecretString)], shell: ExportShell) {
+    let secrets: Vec<_> = secrets.iter().filter(|(key, _)| valid_key(key)).collect();
+    let previous = std::env::var(EXPORTED_KEYS_VAR).unwrap_or_default();
+    for key in previous.split(':').filter(|k| !k.is_empty()) {
+        if !secrets.iter().any(|(k, _)| k == key) && print_line(&shell.unset(key)).is_err()
+        {
+            std::process::exit(0);
+        }
+    }
+
+    let mut lines: Vec<_> = secrets
+        .iter()
+        .filter_map(|(key, value)| export_line(shell, key, value.expose()))
+        .collect();
+    if secrets.is_empty() {
+        lines.push(Zeroizing::new(shell.unset(EXPORTED_KEYS_VAR)));
+    } else {
+        let keys: Vec<&str> = secrets.iter().map(|(k, _)| k.as_str()).collect();
+        lines.extend(export_line(shell, EXPORTED_KEYS_VAR, keys.join(":").as_bytes()));
+    }
+
+    for line in lines {
+        if print_line(&line).is_err() {
+            std::process::exit(0);
+        }
+    }
+}
+
+// Environment variable in which `sven hook` records what it exported: a
+// digest of the directory, then KEY=digest for each exported secret
+const HOOK_STATE_VAR: &str = "SVEN_HOOK";
+
+#[derive(Default, PartialEq)]
+struct HookState {
+    dir: u64,
+    keys: BTreeMap<String, u64>,
+}
+
+impl HookState {
+    // None when the hook hasn't run in this shell yet or the variable is mangled
+    fn from_env() -> Option<Self> {
+        let value = std::env::var(HOOK_STATE_VAR).ok()?;
+        let mut fields = value.split(':');
+        let dir = u64::from_str_radix(fields.next()?, 16).ok()?;
+        let keys = fields
+            .map(|field| {
+                let (key, digest) = field.rsplit_once('=')?;
+                Some((key.to_string(), u64::from_str_radix(digest, 16).ok()?))
+            })
+            .collect::<Option<_>>()?;
+        Some(Self { dir, keys })
+    }
+
+    fn to_value(&self) -> String {
+        let mut value = format!("{:x}", self.dir);
+        for (key, digest) in &self.keys {
+            value.push_str(&format!(":{}={:x}", key, digest));
+        }
+        value
+    }
+}
+
+// Prints only the unsets and exports needed to go from `previous` to
+// `current`, and nothing at all when they match
+fn print_hook(previous: Option<&HookState>, current: &HookState, changed: &[(String, SecretString)], shell: ExportShell) {
+    if shell == ExportShell::Nu {
+        print_hook_record(previous, current, changed);
+        return;
+    }
+
+    let mut lines = Vec::new();
+    if let Some(previous) = previous {
+        for key in previous.keys.keys().filter(|key| !current.keys.contains_key(*key)) {
+            lines.push(Zeroizing::new(shell.unset(key)));
+        }
+    }
+    for (key, value) in changed {
+        lines.extend(export_line(shell, key, value.expose()));
+    }
+    if previous != Some(current) {
+        lines.extend(export_line(shell, HOOK_STATE_VAR, current.to_value().as_bytes()));
+    }
+
+    for line in lines {
+        if print_line(&line).is_err() {
+            std::process::exit(0);
+        }
+    }
+}
+
+// Nushell can't eval code, so its hook gets a JSON record to load, with
+// null for each variable to hide
+fn print_hook_record(previous: Option<&HookState>, current: &HookState, changed: &[(String, SecretString)]) {
+    let mut record: BTreeMap<&str, Option<&str>> = BTreeMap::new();
+    if let Some(previous) = previous {
+        for key in previous.keys.keys().filter(|key| !current.keys.contains_key(*key)) {
+            record.insert(key, None);
+        }
+    }
+    for (key, value) in text_secrets(changed) {
+        record.insert(key, Some(value));
+    }
+    let state = current.to_value();
+    if previous != Some(current) {
+        record.insert(HOOK_STATE_VAR, Some(&state));
+    }
+    if record.is_empty() {
+        return;
+    }
+    let line = Zeroizing::new(serde_json::to_string(&record).unwrap_or_default());
+    if print_line(&line).is_err() {
+        std::process::exit(0);
     }
 }
 
@@ -102,9 +680,30 @@ fn main() -> Result<()> {
     let cli = Cli::parse();
 
     match cli.command {
-        Commands::Unlock => {
+        Commands::Unlock { timeout, max_lifetime } => {
+            let timeouts = LockTimeouts {
+                idle: timeout.map(|d| d.as_secs()),
+                absolute: max_lifetime.map(|d| d.as_secs()),
+            };
+
+            // A running daemon reloads its cache instead
+            if DaemonClient::is_daemon_running().unwrap_or(false) {
+                match DaemonClient::new().and_then(|client| client.unlock(timeouts)) {
+                    Ok(msg) => {
+                        if print_line(&msg).is_err() {
+                            std::process::exit(0);
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("Failed to unlock: {}", e);
+                        std::process::exit(1);
+                    }
+                }
+                return Ok(());
+            }
+
             // Start the daemon
-            if let Err(e) = Daemon::start_daemon() {
+            if let Err(e) = Daemon::start_daemon(timeouts) {
                 eprintln!("Failed to start daemon: {}", e);
                 std::process::exit(1);
             }
@@ -138,10 +737,29 @@ fn main() -> Result<()> {
             // Check if daemon is running
             match DaemonClient::is_daemon_running() {
                 Ok(true) => {
-                    if print_line("Daemon is running. Secrets are unlocked and cached in memory.")
-                        .is_err()
-                    {
-                        std::process::exit(0);
+                    let status = match DaemonClient::new().and_then(|client| client.status()) {
+                        Ok(status) => status,
+                        Err(e) => {
+                            eprintln!("Error checking daemon status: {}", e);
+                            std::process::exit(1);
+                        }
+                    };
+                    let mut lines = Vec::new();
+                    if status.locked {
+                        lines.push("Daemon is running but locked. Run 'sven unlock' to cache secrets again.".to_string());
+                    } else {
+                        lines.push("Daemon is running. Secrets are unlocked and cached in memory.".to_string());
+                        if let Some(secs) = status.idle_remaining {
+                            lines.push(format!("Locks after {} without use", daemon::format_duration(secs)));
+                        }
+                        if let Some(secs) = status.absolute_remaining {
+                            lines.push(format!("Locks in {} regardless of use", daemon::format_duration(secs)));
+                        }
+                    }
+                    for line in lines {
+                        if print_line(&line).is_err() {
+                            std::process::exit(0);
+                        }
                     }
                 }
                 Ok(false) => {
@@ -157,6 +775,262 @@ fn main() -> Result<()> {
                 }
             }
         }
+        Commands::Recipients { command } => {
+            // Recipients only change ciphertexts, so the daemon cache stays valid
+            let mut db = Database::new()?;
+            let msg = match command {
+                RecipientCommands::Add { key_id } => {
+                    let (key_id, reencrypted) = db.add_recipient(&key_id)?;
+                    audit_ids(AuditAction::Reencrypt, &reencrypted);
+                    format!("Added recipient {} and re-encrypted all secrets", key_id)
+                }
+                RecipientCommands::Remove { key_id } => {
+                    let reencrypted = db.remove_recipient(&key_id)?;
+                    audit_ids(AuditAction::Reencrypt, &reencrypted);
+                    format!("Removed recipient {} and re-encrypted all secrets", key_id)
+                }
+                RecipientCommands::List => {
+                    for description in db.list_recipients() {
+                        if print_line(&format!("  {}", description)).is_err() {
+                            std::process::exit(0);
+                        }
+                    }
+                    return Ok(());
+                }
+            };
+            if print_line(&msg).is_err() {
+                std::process::exit(0);
+            }
+        }
+        Commands::Backup { file, recipient, symmetric } => {
+            use std::io::Write;
+            use std::os::unix::fs::OpenOptionsExt;
+
+            let mut db = Database::new()?;
+            let (armored, ids) = db.backup(&recipient, symmetric)?;
+            let mut out = std::fs::OpenOptions::new()
+                .create(true)
+                .write(true)
+                .truncate(true)
+                .mode(0o600)
+                .open(&file)?;
+            out.write_all(&armored)?;
+            audit_ids(AuditAction::Backup, &ids);
+            if print_line(&format!("Wrote backup to {}", file.display())).is_err() {
+                std::process::exit(0);
+            }
+        }
+        Commands::Restore { file, mode } => {
+            let mut db = Database::new()?;
+            let summary = db.restore(&std::fs::read(&file)?, mode)?;
+            audit_ids(AuditAction::Restore, &summary.added);
+            audit_ids(AuditAction::Restore, &summary.updated);
+            audit_ids(AuditAction::Remove, &summary.removed);
+            for line in summary.lines() {
+                if print_line(&line).is_err() {
+                    std::process::exit(0);
+                }
+            }
+            if DaemonClient::is_daemon_running().unwrap_or(false) {
+                if let Err(e) = DaemonClient::new().and_then(|client| client.reload()) {
+                    eprintln!("Failed to reload the daemon, run 'sven reload' to pick up restored secrets: {}", e);
+                }
+            }
+        }
+        Commands::History { key, scope, profile, retention } => {
+            let mut db = Database::new()?;
+            if let Some(retention) = retention {
+                db.set_history_retention(retention)?;
+                if print_line(&format!("Keeping {} previous values per secret", retention)).is_err() {
+                    std::process::exit(0);
+                }
+            }
+            if let Some(key) = key {
+                let profile = db.resolve_profile(profile.as_deref())?;
+                let scope = db::normalize_scope(scope.as_deref())?;
+                let versions = db.history(&profile, &key, &scope)?;
+                let mut lines = Vec::new();
+                if versions.is_empty() {
+                    lines.push(format!("No history for {}", key));
+                } else {
+                    lines.push(format!("History of {}:", key));
+                    for (version, replaced_at) in versions {
+                        lines.push(format!("  {:>3}  replaced {}", version, replaced_at));
+                    }
+                }
+                for line in lines {
+                    if print_line(&line).is_err() {
+                        std::process::exit(0);
+                    }
+                }
+            }
+        }
+        Commands::Init { shell } => {
+            if print_line(shell.script().trim_end()).is_err() {
+                std::process::exit(0);
+            }
+        }
+        Commands::InstallHook { shell, rc_file } => {
+            let shell = match shell {
+                Some(shell) => shell,
+                None => shell::InitShell::detect()?,
+            };
+            let (rc_file, outcome) = shell::install_hook(shell, rc_file)?;
+            let msg = match outcome {
+                shell::InstallOutcome::Installed => format!("Added the sven hook to {}", rc_file.display()),
+                shell::InstallOutcome::Updated => format!("Updated the sven hook in {}", rc_file.display()),
+                shell::InstallOutcome::Unchanged => format!("The sven hook is already in {}", rc_file.display()),
+            };
+            if print_line(&msg).is_err() {
+                std::process::exit(0);
+            }
+        }
+        Commands::Hook { shell, profile } => {
+            if shell == ExportShell::Nu {
+                // Best effort; a failure must not break the prompt
+                let _ = shell::refresh_nu_script();
+            }
+            let dir = current_dir_string()?;
+            let previous = HookState::from_env();
+            let known: Vec<(String, u64)> = previous
+                .iter()
+                .flat_map(|state| state.keys.iter().map(|(key, digest)| (key.clone(), *digest)))
+                .collect();
+            let from_daemon = if DaemonClient::is_daemon_running().unwrap_or(false) {
+                DaemonClient::new()
+                    .and_then(|client| client.get_secret_changes(profile.as_deref(), &dir, &known))
+                    .ok()
+            } else {
+                None
+            };
+            let dir_digest = secret::digest(dir.as_bytes());
+            let (digests, changed) = match from_daemon {
+                Some(changes) => changes,
+                // Decrypting on every prompt would be too slow, so without the
+                // daemon secrets are only reloaded when the directory changes
+                None if previous.as_ref().is_some_and(|state| state.dir == dir_digest) => return Ok(()),
+                None => {
+                    let mut db = Database::new()?;
+                    let profile = db.resolve_profile(profile.as_deref())?;
+                    let secrets = db.get_secrets_for_dir(&profile, std::path::Path::new(&dir))?;
+                    let digests = secrets.iter().map(|(key, value)| (key.clone(), value.digest())).collect();
+                    let changed: Vec<_> = secrets
+                        .into_iter()
+                        .filter(|(key, value)| !known.contains(&(key.clone(), value.digest())))
+                        .collect();
+                    let keys: Vec<&str> = changed.iter().map(|(key, _)| key.as_str()).collect();
+                    audit(AuditAction::Read, &profile, &keys, None);
+                    (digests, changed)
+                }
+            };
+            // Invalid keys are skipped without the warning export gives, which
+            // would otherwise repeat at every prompt
+            let keys = digests.into_iter().filter(|(key, _)| db::validate_key(key).is_ok()).collect();
+            let changed: Vec<_> = changed.into_iter().filter(|(key, _)| db::validate_key(key).is_ok()).collect();
+            let current = HookState { dir: dir_digest, keys };
+            print_hook(previous.as_ref(), &current, &changed, shell);
+        }
+        Commands::Doctor => {
+            let db = Database::new()?;
+            let invalid: Vec<String> = db
+                .encrypted_rows()?
+                .into_iter()
+                .filter(|(_, key, _, _)| db::validate_key(key).is_err())
+                .map(|(profile, key, scope, _)| format!("  {}", backup::entry_label(&profile, &key, &scope)))
+                .collect();
+            let healthy = invalid.is_empty();
+            let mut lines = Vec::new();
+            if healthy {
+                lines.push("No problems found".to_string());
+            } else {
+                lines.push("Keys that aren't valid environment variable names (skipped by export, hook and run):".to_string());
+                lines.extend(invalid);
+                lines.push("Rename each with 'sven get OLD --raw | sven add NEW --stdin' and 'sven remove OLD'".to_string());
+            }
+            for line in &lines {
+                if print_line(line).is_err() {
+                    std::process::exit(0);
+                }
+            }
+            if !healthy {
+                std::process::exit(1);
+            }
+        }
+        Commands::ClearClipboard { after } => {
+            let digest = std::env::var(clipboard::DIGEST_VAR)
+                .ok()
+                .and_then(|digest| u64::from_str_radix(&digest, 16).ok())
+                .ok_or_else(|| anyhow::anyhow!("{} is not set", clipboard::DIGEST_VAR))?;
+            clipboard::clear_after(digest, after)?;
+        }
+        Commands::Audit { key, since } => {
+            let since = since.map(|since| db::unix_now() - since.as_secs() as i64);
+            let entries = AuditLog::read(key.as_deref(), since)?;
+            if entries.is_empty() && print_line("No audit entries found").is_err() {
+                std::process::exit(0);
+            }
+            for entry in entries {
+                if print_line(&entry.line()).is_err() {
+                    std::process::exit(0);
+                }
+            }
+        }
+        Commands::Rekey { to, from } => {
+            let mut db = Database::new()?;
+            let (key_id, reencrypted) = db.rekey(from.as_deref(), to.as_deref())?;
+            audit_ids(AuditAction::Reencrypt, &reencrypted);
+            if print_line(&format!("Re-encrypted all secrets for key {}", key_id)).is_err() {
+                std::process::exit(0);
+            }
+        }
+        Commands::Lock => {
+            match DaemonClient::is_daemon_running() {
+                Ok(true) => match DaemonClient::new().and_then(|client| client.lock()) {
+                    Ok(msg) => {
+                        if print_line(&msg).is_err() {
+                            std::process::exit(0);
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("Failed to lock: {}", e);
+                        std::process::exit(1);
+                    }
+                },
+                Ok(false) => {
+                    if print_line("Daemon is not running.").is_err() {
+                        std::process::exit(0);
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Error checking daemon status: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Commands::Reload => {
+            match DaemonClient::is_daemon_running() {
+                Ok(true) => match DaemonClient::new().and_then(|client| client.reload()) {
+                    Ok(msg) => {
+                        if print_line(&msg).is_err() {
+                            std::process::exit(0);
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("Failed to reload: {}", e);
+                        std::process::exit(1);
+                    }
+                },
+                Ok(false) => {
+                    if print_line("Daemon is not running.").is_err() {
+                        std::process::exit(0);
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Error checking daemon status: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
         Commands::Stop => {
             // Stop the daemon
             match DaemonClient::is_daemon_running() {
@@ -168,6 +1042,18 @@ fn main() -> Result<()> {
                                 std::process::exit(0);
                             }
                         }
+                        // A daemon from another version can't be asked to stop, so signal it
+                        Err(error::SvenError::ProtocolMismatch(_)) => match Daemon::terminate() {
+                            Ok(()) => {
+                                if print_line("Daemon from a different sven version stopped").is_err() {
+                                    std::process::exit(0);
+                                }
+                            }
+                            Err(e) => {
+                                eprintln!("Failed to stop daemon: {}", e);
+                                std::process::exit(1);
+                            }
+                        },
                         Err(e) => {
                             eprintln!("Failed to stop daemon: {}", e);
                             std::process::exit(1);
@@ -187,15 +1073,31 @@ fn main() -> Result<()> {
         }
         // For other commands, try to use the daemon if it's running
         _ => {
-            let use_daemon = match DaemonClient::is_daemon_running() {
-                Ok(running) => running,
-                Err(_) => false,
+            // A locked daemon holds no secrets, so fall back to decrypting on demand
+            let client = match DaemonClient::is_daemon_running() {
+                Ok(true) => match DaemonClient::new()
+                    .and_then(|client| client.status().map(|status| (client, status)))
+                {
+                    Ok((client, status)) if !status.locked => Some(client),
+                    Ok(_) => None,
+                    Err(e) => {
+                        eprintln!("Not using the daemon: {}", e);
+                        None
+                    }
+                },
+                _ => None,
             };
 
-            if use_daemon {
-                let client = DaemonClient::new()?;
+            if let Some(client) = client {
                 match cli.command {
-                    Commands::Add { key, value } => match client.add_secret(&key, &value) {
+                    Commands::Add { key, value, stdin, from_file, scope, profile, description, tags } => match db::validate_key(&key)
+                        .and_then(|_| db::normalize_scope(scope.as_deref()))
+                        .and_then(|scope| {
+                            let value = read_secret_value(&key, value, stdin, from_file.as_deref())?;
+                            let meta = secret_meta(description, tags);
+                            client.add_secret(profile.as_deref(), &key, &value, &scope, &meta)
+                        })
+                    {
                         Ok(msg) => {
                             if print_line(&msg).is_err() {
                                 std::process::exit(0);
@@ -206,7 +1108,9 @@ fn main() -> Result<()> {
                             std::process::exit(1);
                         }
                     },
-                    Commands::Remove { key } => match client.remove_secret(&key) {
+                    Commands::Remove { key, scope, profile } => match db::normalize_scope(scope.as_deref())
+                        .and_then(|scope| client.remove_secret(profile.as_deref(), &key, &scope))
+                    {
                         Ok(msg) => {
                             if print_line(&msg).is_err() {
                                 std::process::exit(0);
@@ -217,41 +1121,96 @@ fn main() -> Result<()> {
                             std::process::exit(1);
                         }
                     },
-                    Commands::List => match client.list_secrets() {
-                        Ok(secrets) => {
-                            if secrets.is_empty() {
-                                if print_line("No secrets found").is_err() {
-                                    std::process::exit(0);
-                                }
-                            } else {
-                                if print_line("Secrets:").is_err() {
-                                    std::process::exit(0);
-                                }
-                                for key in secrets {
-                                    if print_line(&format!("  {}", key)).is_err() {
-                                        std::process::exit(0);
-                                    }
-                                }
+                    Commands::Get { key, raw, clip, clear_after, profile } => {
+                        match client.get_secret(&key, profile.as_deref(), &current_dir_string()?) {
+                            Ok(value) => output_secret(&key, value, raw, clip, clear_after),
+                            Err(e) => {
+                                eprintln!("Failed to get secret: {}", e);
+                                std::process::exit(1);
                             }
                         }
+                    }
+                    Commands::List { profile, long, tag } => match client.list_secrets(profile.as_deref(), tag.as_deref()) {
+                        Ok(secrets) => print_secret_list(&secrets, long),
                         Err(e) => {
                             eprintln!("Failed to list secrets: {}", e);
                             std::process::exit(1);
                         }
                     },
-                    Commands::Export { shell } => match client.get_secrets(&shell) {
-                        Ok(secrets) => {
-                            for (key, value) in secrets {
-                                if print_line(&format_export(&key, &value, &shell)).is_err() {
+                    Commands::Export { shell, format, profile } => {
+                        match client.get_secrets(profile.as_deref(), &current_dir_string()?) {
+                            Ok(secrets) => print_secrets(&secrets, format, shell),
+                            Err(e) => {
+                                eprintln!("Failed to export secrets: {}", e);
+                                std::process::exit(1);
+                            }
+                        }
+                    }
+                    Commands::Import { file, format, scope, profile } => {
+                        let result = db::normalize_scope(scope.as_deref()).and_then(|scope| {
+                            let entries = read_import_file(&file, format)?;
+                            for (key, value) in &entries {
+                                client.add_secret(profile.as_deref(), key, value, &scope, &db::SecretMeta::default())?;
+                            }
+                            Ok(entries.len())
+                        });
+                        match result {
+                            Ok(count) => {
+                                if print_line(&format!("Imported {} secrets from {}", count, file.display())).is_err() {
                                     std::process::exit(0);
                                 }
                             }
+                            Err(e) => {
+                                eprintln!("Failed to import secrets: {}", e);
+                                std::process::exit(1);
+                            }
+                        }
+                    }
+                    Commands::Run { only, profile, command } => {
+                        match client.get_secrets(profile.as_deref(), &current_dir_string()?) {
+                            Ok(secrets) => run_with_secrets(secrets, &only, &command)?,
+                            Err(e) => {
+                                eprintln!("Failed to load secrets: {}", e);
+                                std::process::exit(1);
+                            }
+                        }
+                    }
+                    Commands::Rollback { key, version, scope, profile } => match db::normalize_scope(scope.as_deref())
+                        .and_then(|scope| client.rollback(profile.as_deref(), &key, &scope, version))
+                    {
+                        Ok(msg) => {
+                            if print_line(&msg).is_err() {
+                                std::process::exit(0);
+                            }
                         }
                         Err(e) => {
-                            eprintln!("Failed to export secrets: {}", e);
+                            eprintln!("Failed to roll back secret: {}", e);
                             std::process::exit(1);
                         }
                     },
+                    Commands::Profile { command } => {
+                        let result = match command {
+                            ProfileCommands::List => client.list_profiles().map(|(names, active)| {
+                                print_profiles(&names, &active);
+                                None
+                            }),
+                            ProfileCommands::Create { name } => client.create_profile(&name).map(Some),
+                            ProfileCommands::Use { name } => client.use_profile(&name).map(Some),
+                            ProfileCommands::Delete { name } => client.delete_profile(&name).map(Some),
+                        };
+                        match result {
+                            Ok(Some(msg)) => {
+                                if print_line(&msg).is_err() {
+                                    std::process::exit(0);
+                                }
+                            }
+                            Ok(None) => {}
+                            Err(e) => {
+                                eprintln!("Failed to manage profiles: {}", e);
+                                std::process::exit(1);
+                            }
+                        }
+                    }
                     _ => unreachable!(),
                 }
             } else {
@@ -259,39 +1218,101 @@ fn main() -> Result<()> {
                 let mut db = Database::new()?;
 
                 match cli.command {
-                    Commands::Add { key, value } => {
-                        db.add_secret(&key, &value)?;
+                    Commands::Add { key, value, stdin, from_file, scope, profile, description, tags } => {
+                        // Before prompting, so a bad key doesn't waste a typed value
+                        db::validate_key(&key)?;
+                        let profile = db.resolve_profile(profile.as_deref())?;
+                        let scope = db::normalize_scope(scope.as_deref())?;
+                        let value = read_secret_value(&key, value, stdin, from_file.as_deref())?;
+                        db.add_secret(&profile, &key, value.expose(), &scope, &secret_meta(description, tags))?;
+                        audit(AuditAction::Add, &profile, &[&key], Some(&scope));
                         if print_line(&format!("Added secret: {}", key)).is_err() {
                             std::process::exit(0);
                         }
                     }
-                    Commands::Remove { key } => {
-                        db.remove_secret(&key)?;
+                    Commands::Remove { key, scope, profile } => {
+                        let profile = db.resolve_profile(profile.as_deref())?;
+                        let scope = db::normalize_scope(scope.as_deref())?;
+                        db.remove_secret(&profile, &key, &scope)?;
+                        audit(AuditAction::Remove, &profile, &[&key], Some(&scope));
                         if print_line(&format!("Removed secret: {}", key)).is_err() {
                             std::process::exit(0);
                         }
                     }
-                    Commands::List => {
-                        let secrets = db.list_secrets()?;
-                        if secrets.is_empty() {
-                            if print_line("No secrets found").is_err() {
-                                std::process::exit(0);
+                    Commands::Get { key, raw, clip, clear_after, profile } => {
+                        let profile = db.resolve_profile(profile.as_deref())?;
+                        let dir = current_dir_string()?;
+                        let value = db.get_secret(&profile, &key, std::path::Path::new(&dir))?;
+                        if value.is_some() {
+                            audit(AuditAction::Read, &profile, &[&key], None);
+                        }
+                        output_secret(&key, value, raw, clip, clear_after);
+                    }
+                    Commands::List { profile, long, tag } => {
+                        let profile = db.resolve_profile(profile.as_deref())?;
+                        print_secret_list(&db.list_secrets(&profile, tag.as_deref())?, long);
+                    }
+                    Commands::Export { shell, format, profile } => {
+                        let profile = db.resolve_profile(profile.as_deref())?;
+                        let dir = current_dir_string()?;
+                        let secrets = db.get_secrets_for_dir(&profile, std::path::Path::new(&dir))?;
+                        let keys: Vec<&str> = secrets.iter().map(|(key, _)| key.as_str()).collect();
+                        audit(AuditAction::Read, &profile, &keys, None);
+                        print_secrets(&secrets, format, shell);
+                    }
+                    Commands::Import { file, format, scope, profile } => {
+                        let profile = db.resolve_profile(profile.as_deref())?;
+                        let scope = db::normalize_scope(scope.as_deref())?;
+                        let entries = read_import_file(&file, format)?;
+                        for (key, value) in &entries {
+                            db.add_secret(&profile, key, value.expose(), &scope, &db::SecretMeta::default())?;
+                            audit(AuditAction::Add, &profile, &[key], Some(&scope));
+                        }
+                        if print_line(&format!("Imported {} secrets from {}", entries.len(), file.display())).is_err() {
+                            std::process::exit(0);
+                        }
+                    }
+                    Commands::Run { only, profile, command } => {
+                        let profile = db.resolve_profile(profile.as_deref())?;
+                        let dir = current_dir_string()?;
+                        let secrets = db.get_secrets_for_dir(&profile, std::path::Path::new(&dir))?;
+                        let keys: Vec<&str> = secrets.iter().map(|(key, _)| key.as_str()).collect();
+                        audit(AuditAction::Read, &profile, &keys, None);
+                        drop(db);
+                        run_with_secrets(secrets, &only, &command)?;
+                    }
+                    Commands::Rollback { key, version, scope, profile } => {
+                        let profile = db.resolve_profile(profile.as_deref())?;
+                        let scope = db::normalize_scope(scope.as_deref())?;
+                        db.rollback(&profile, &key, &scope, version)?;
+                        audit(AuditAction::Rollback, &profile, &[&key], Some(&scope));
+                        if print_line(&format!("Rolled back {} to version {}", key, version)).is_err() {
+                            std::process::exit(0);
+                        }
+                    }
+                    Commands::Profile { command } => {
+                        let msg = match command {
+                            ProfileCommands::List => {
+                                print_profiles(&db.list_profiles()?, &db.active_profile()?);
+                                None
                             }
-                        } else {
-                            if print_line("Secrets:").is_err() {
-                                std::process::exit(0);
+                            ProfileCommands::Create { name } => {
+                                db.create_profile(&name)?;
+                                Some(format!("Created profile: {}", name))
                             }
-                            for key in secrets {
-                                if print_line(&format!("  {}", key)).is_err() {
-                                    std::process::exit(0);
+                            ProfileCommands::Use { name } => {
+                                db.use_profile(&name)?;
+                                Some(format!("Switched to profile: {}", name))
+                            }
+                            ProfileCommands::Delete { name } => {
+                                for (key, scope) in db.delete_profile(&name)? {
+                                    audit(AuditAction::Remove, &name, &[&key], Some(&scope));
                                 }
+                                Some(format!("Deleted profile: {}", name))
                             }
-                        }
-                    }
-                    Commands::Export { shell } => {
-                        let secrets = db.get_all_secrets()?;
-                        for (key, value) in secrets {
-                            if print_line(&format_export(&key, &value, &shell)).is_err() {
+                        };
+                        if let Some(msg) = msg {
+                            if print_line(&msg).is_err() {
                                 std::process::exit(0);
                             }
                         }
@@ -304,3 +1325,18 @@ fn main() -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn binary_values_are_read_unchanged() {
+        let bytes = [0xff, 0x00, 0xfe, b'\n', 0x80];
+        let path = std::env::temp_dir().join(format!("sven-test-value-{}", std::process::id()));
+        std::fs::write(&path, bytes).unwrap();
+        let value = read_secret_value("KEY", None, false, Some(&path));
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(value.unwrap().expose(), bytes);
+    }
+}
@@ -0,0 +1,230 @@
+use crate::db;
+use crate::error::{Result, SvenError};
+use rusqlite::{params, Connection, Transaction};
+use std::path::{Path, PathBuf};
+
+// One schema change. Migrations run in order, each in its own transaction,
+// and `PRAGMA user_version` records the last one applied.
+struct Migration {
+    version: u32,
+    description: &'static str,
+    // Rebuilds or drops data; a copy of the database is written first
+    destructive: bool,
+    apply: fn(&Transaction) -> Result<()>,
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "variables with profiles, scopes and metadata",
+        destructive: true,
+        apply: initial_schema,
+    },
+    Migration {
+        version: 2,
+        description: "variable value history",
+        destructive: false,
+        apply: variable_history,
+    },
+];
+
+// Brings the database up to the latest schema version
+pub fn run(conn: &mut Connection, db_path: &Path) -> Result<()> {
+    let current: u32 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    let latest = MIGRATIONS.last().map(|m| m.version).unwrap_or(0);
+    if current > latest {
+        return Err(SvenError::ConfigError(format!(
+            "Database schema version {} is newer than this sven supports ({}); please upgrade",
+            current, latest
+        )));
+    }
+
+    let pending: Vec<&Migration> = MIGRATIONS.iter().filter(|m| m.version > current).collect();
+    if pending.is_empty() {
+        return Ok(());
+    }
+
+    if pending.iter().any(|m| m.destructive) && has_table(conn, "variables")? {
+        let backup_path = backup_path(db_path, current);
+        conn.execute("VACUUM INTO ?1", params![backup_path.to_string_lossy()])?;
+        eprintln!(
+            "Upgrading database schema; a copy of the old database was saved to {}",
+            backup_path.display()
+        );
+    }
+
+    for migration in pending {
+        let tx = conn.transaction()?;
+        (migration.apply)(&tx).map_err(|e| {
+            SvenError::ConfigError(format!(
+                "Migration {} ({}) failed: {}",
+                migration.version, migration.description, e
+            ))
+        })?;
+        tx.pragma_update(None, "user_version", migration.version)?;
+        tx.commit()?;
+    }
+
+    Ok(())
+}
+
+// A name no file has yet, since VACUUM INTO refuses to overwrite one left by
+// an earlier failed upgrade or by upgrading an older copy of the database
+fn backup_path(db_path: &Path, version: u32) -> PathBuf {
+    let stem = format!(
+        "{}.v{}.{}",
+        db_path.file_name().unwrap_or_default().to_string_lossy(),
+        version,
+        db::unix_now()
+    );
+    (0..)
+        .map(|n| match n {
+            0 => db_path.with_file_name(format!("{}.bak", stem)),
+            n => db_path.with_file_name(format!("{}.{}.bak", stem, n)),
+        })
+        .find(|path| !path.exists())
+        .expect("unbounded range")
+}
+
+fn has_table(conn: &Connection, table: &str) -> Result<bool> {
+    let count: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?1",
+        params![table],
+        |row| row.get(0),
+    )?;
+    Ok(count > 0)
+}
+
+fn has_column(conn: &Connection, table: &str, column: &str) -> Result<bool> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let columns = stmt
+        .query_map([], |row| row.get::<_, String>(1))?
+        .collect::<std::result::Result<Vec<String>, _>>()?;
+    Ok(columns.iter().any(|c| c == column))
+}
+
+// Creates the schema on fresh installs and upgrades stores written before
+// versioning existed, which may lack profiles, scopes or metadata columns
+fn initial_schema(tx: &Transaction) -> Result<()> {
+    if has_table(tx, "variables")? && !has_column(tx, "variables", "profile")? {
+        let scope = if has_column(tx, "variables", "scope")? {
+            "scope"
+        } else {
+            "''"
+        };
+        tx.execute("ALTER TABLE variables RENAME TO variables_old", [])?;
+        tx.execute_batch(&format!(
+            "CREATE TABLE variables (
+                profile TEXT NOT NULL DEFAULT 'default',
+                key TEXT NOT NULL,
+                scope TEXT NOT NULL DEFAULT '',
+                value TEXT NOT NULL,
+                PRIMARY KEY (profile, key, scope)
+            );
+            INSERT INTO variables (profile, key, scope, value)
+                SELECT 'default', key, {}, value FROM variables_old;
+            DROP TABLE variables_old;",
+            scope
+        ))?;
+    }
+
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS variables (
+            profile TEXT NOT NULL DEFAULT 'default',
+            key TEXT NOT NULL,
+            scope TEXT NOT NULL DEFAULT '',
+            value TEXT NOT NULL,
+            PRIMARY KEY (profile, key, scope)
+        )",
+        [],
+    )?;
+
+    // Existing rows get NULL timestamps since their history is unknown
+    for (column, definition) in [
+        ("description", "TEXT"),
+        ("tags", "TEXT NOT NULL DEFAULT ''"),
+        ("created_at", "INTEGER"),
+        ("updated_at", "INTEGER"),
+        ("last_accessed", "INTEGER"),
+    ] {
+        if !has_column(tx, "variables", column)? {
+            tx.execute(
+                &format!("ALTER TABLE variables ADD COLUMN {} {}", column, definition),
+                [],
+            )?;
+        }
+    }
+
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS profiles (
+            name TEXT PRIMARY KEY
+        )",
+        [],
+    )?;
+    tx.execute("INSERT OR IGNORE INTO profiles (name) VALUES ('default')", [])?;
+
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS config (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+        )",
+        [],
+    )?;
+
+    Ok(())
+}
+
+// Previous ciphertexts of each (profile, key, scope), kept for rollback
+fn variable_history(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE variable_history (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            profile TEXT NOT NULL,
+            key TEXT NOT NULL,
+            scope TEXT NOT NULL,
+            value TEXT NOT NULL,
+            replaced_at INTEGER NOT NULL
+        );
+        CREATE INDEX variable_history_lookup ON variable_history (profile, key, scope, id);",
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A store from before schema versioning, which the first migration rebuilds
+    fn legacy_database(path: &Path) -> Connection {
+        let conn = Connection::open(path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE variables (key TEXT PRIMARY KEY, value TEXT NOT NULL);
+             INSERT INTO variables (key, value) VALUES ('TOKEN', 'ciphertext');",
+        )
+        .unwrap();
+        conn
+    }
+
+    #[test]
+    fn destructive_migration_runs_again_beside_an_earlier_backup() {
+        let dir = std::env::temp_dir().join(format!("sven-migrations-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("envs.sqlite");
+
+        // The second run stands in for reopening an old copy of the store
+        for _ in 0..2 {
+            let _ = std::fs::remove_file(&path);
+            let mut conn = legacy_database(&path);
+            run(&mut conn, &path).unwrap();
+            let version: u32 = conn.query_row("PRAGMA user_version", [], |row| row.get(0)).unwrap();
+            assert_eq!(version, MIGRATIONS.last().unwrap().version);
+        }
+
+        let backups = std::fs::read_dir(&dir)
+            .unwrap()
+            .filter(|entry| entry.as_ref().unwrap().file_name().to_string_lossy().ends_with(".bak"))
+            .count();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(backups, 2);
+    }
+}
@@ -0,0 +1,218 @@
+use crate::error::{Result, SvenError};
+use serde::{de::DeserializeOwned, Serialize};
+use std::io::{BufRead, Read, Write};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use zeroize::Zeroizing;
+
+// Bumped whenever DaemonCommand or DaemonResponse change incompatibly;
+// version 2 added Lock, Reload and GetSecretChanges and dropped the shell
+// from GetSecrets
+pub const PROTOCOL_VERSION: u32 = 2;
+
+// Upper bound on a frame, so a corrupt length can't trigger a huge allocation
+const MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+
+// The handshake is a single text line, so even daemons and clients that
+// predate framing fail cleanly instead of waiting for data that never comes
+const HANDSHAKE_PREFIX: &str = "sven-rpc";
+
+fn handshake_line() -> String {
+    format!("{} {}\n", HANDSHAKE_PREFIX, PROTOCOL_VERSION)
+}
+
+fn parse_handshake(line: &str) -> Option<u32> {
+    line.trim_end()
+        .strip_prefix(HANDSHAKE_PREFIX)
+        .and_then(|version| version.trim().parse().ok())
+}
+
+pub fn write_handshake(writer: &mut impl Write) -> Result<()> {
+    writer.write_all(handshake_line().as_bytes())?;
+    writer.flush()?;
+    Ok(())
+}
+
+pub async fn write_handshake_async(writer: &mut (impl AsyncWrite + Unpin)) -> Result<()> {
+    writer.write_all(handshake_line().as_bytes()).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+// Returns the peer's protocol version, or None if it closed the connection
+// or sent something other than a handshake
+pub fn read_handshake(reader: &mut impl BufRead) -> Result<Option<u32>> {
+    let mut line = Zeroizing::new(String::new());
+    // Bounded like frames, in case an old client sends a long JSON line
+    reader.by_ref().take(MAX_FRAME_LEN as u64).read_line(&mut line)?;
+    Ok(parse_handshake(&line))
+}
+
+pub async fn read_handshake_async(reader: &mut (impl AsyncBufRead + Unpin)) -> Result<Option<u32>> {
+    let mut line = Zeroizing::new(String::new());
+    (&mut *reader).take(MAX_FRAME_LEN as u64).read_line(&mut line).await?;
+    Ok(parse_handshake(&line))
+}
+
+// Error for a peer that answered the handshake with `version`
+pub fn mismatch_error(version: Option<u32>) -> SvenError {
+    let daemon = match version {
+        Some(version) => format!("protocol version {}", version),
+        None => "an older protocol".to_string(),
+    };
+    SvenError::ProtocolMismatch(format!(
+        "the running daemon speaks {} but this sven speaks version {}; restart it with 'sven stop' and 'sven unlock'",
+        daemon, PROTOCOL_VERSION
+    ))
+}
+
+// A message as a 4-byte big-endian length followed by its JSON
+fn encode_frame<T: Serialize>(message: &T) -> Result<Zeroizing<Vec<u8>>> {
+    let payload = Zeroizing::new(serde_json::to_vec(message)?);
+    if payload.len() > MAX_FRAME_LEN {
+        return Err(SvenError::ConfigError(format!(
+            "Message of {} bytes exceeds the {} byte limit",
+            payload.len(),
+            MAX_FRAME_LEN
+        )));
+    }
+    let mut frame = Zeroizing::new(Vec::with_capacity(4 + payload.len()));
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&payload);
+    Ok(frame)
+}
+
+fn frame_len(header: [u8; 4]) -> Result<usize> {
+    let len = u32::from_be_bytes(header) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(SvenError::ConfigError(format!(
+            "Frame of {} bytes exceeds the {} byte limit",
+            len, MAX_FRAME_LEN
+        )));
+    }
+    Ok(len)
+}
+
+fn decode_frame<T: DeserializeOwned>(payload: &[u8]) -> Result<T> {
+    serde_json::from_slice(payload)
+        .map_err(|e| SvenError::ConfigError(format!("Invalid message: {}", e)))
+}
+
+pub fn write_frame<T: Serialize>(writer: &mut impl Write, message: &T) -> Result<()> {
+    writer.write_all(&encode_frame(message)?)?;
+    writer.flush()?;
+    Ok(())
+}
+
+pub async fn write_frame_async<T: Serialize>(writer: &mut (impl AsyncWrite + Unpin), message: &T) -> Result<()> {
+    writer.write_all(&encode_frame(message)?).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+// Reads one message written by `write_frame`; None when the peer closed the
+// connection between frames
+pub fn read_frame<T: DeserializeOwned>(reader: &mut impl Read) -> Result<Option<T>> {
+    let mut header = [0u8; 4];
+    match reader.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let mut payload = Zeroizing::new(vec![0u8; frame_len(header)?]);
+    reader.read_exact(&mut payload)?;
+    decode_frame(&payload).map(Some)
+}
+
+pub async fn read_frame_async<T: DeserializeOwned>(reader: &mut (impl AsyncRead + Unpin)) -> Result<Option<T>> {
+    let mut header = [0u8; 4];
+    match reader.read_exact(&mut header).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let mut payload = Zeroizing::new(vec![0u8; frame_len(header)?]);
+    reader.read_exact(&mut payload).await?;
+    decode_frame(&payload).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secret::SecretString;
+
+    type Message = (u64, Vec<(String, SecretString)>);
+
+    fn message(value: &str) -> Message {
+        (7, vec![("KEY".to_string(), value.to_string().into())])
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        let mut buffer = Vec::new();
+        write_frame(&mut buffer, &message("first")).unwrap();
+        write_frame(&mut buffer, &message("second")).unwrap();
+
+        let mut reader = buffer.as_slice();
+        assert_eq!(read_frame::<Message>(&mut reader).unwrap(), Some(message("first")));
+        assert_eq!(read_frame::<Message>(&mut reader).unwrap(), Some(message("second")));
+        assert_eq!(read_frame::<Message>(&mut reader).unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn async_frames_round_trip() {
+        let mut buffer = Vec::new();
+        write_frame_async(&mut buffer, &message("value")).await.unwrap();
+        let mut reader = buffer.as_slice();
+        assert_eq!(read_frame_async::<Message>(&mut reader).await.unwrap(), Some(message("value")));
+        assert_eq!(read_frame_async::<Message>(&mut reader).await.unwrap(), None);
+    }
+
+    #[test]
+    fn values_may_contain_newlines() {
+        // Line-based messages would end at the first newline
+        let mut buffer = Vec::new();
+        write_frame(&mut buffer, &message("line one\nline two\n")).unwrap();
+        write_frame(&mut buffer, &message("\n")).unwrap();
+        let mut reader = buffer.as_slice();
+        assert_eq!(read_frame::<Message>(&mut reader).unwrap(), Some(message("line one\nline two\n")));
+        assert_eq!(read_frame::<Message>(&mut reader).unwrap(), Some(message("\n")));
+    }
+
+    #[test]
+    fn oversized_frames_are_rejected() {
+        let header = (MAX_FRAME_LEN as u32 + 1).to_be_bytes();
+        let err = read_frame::<Message>(&mut header.as_slice()).unwrap_err();
+        assert!(err.to_string().contains("exceeds"), "{}", err);
+
+        let err = write_frame(&mut Vec::new(), &"x".repeat(MAX_FRAME_LEN)).unwrap_err();
+        assert!(err.to_string().contains("exceeds"), "{}", err);
+    }
+
+    #[test]
+    fn truncated_frames_are_errors() {
+        let mut buffer = Vec::new();
+        write_frame(&mut buffer, &message("value")).unwrap();
+        buffer.pop();
+        assert!(read_frame::<Message>(&mut buffer.as_slice()).is_err());
+    }
+
+    #[test]
+    fn handshakes_carry_the_version() {
+        let mut buffer = Vec::new();
+        write_handshake(&mut buffer).unwrap();
+        assert_eq!(read_handshake(&mut buffer.as_slice()).unwrap(), Some(PROTOCOL_VERSION));
+        assert_eq!(read_handshake(&mut &b"sven-rpc 1\n"[..]).unwrap(), Some(1));
+    }
+
+    #[test]
+    fn handshake_mismatches_are_reported() {
+        // A newer daemon, one that predates the handshake and one that hung up
+        for (reply, version) in [(&b"sven-rpc 99\n"[..], Some(99)), (b"{\"Error\":\"x\"}\n", None), (b"", None)] {
+            assert_eq!(read_handshake(&mut &reply[..]).unwrap(), version);
+        }
+        let err = mismatch_error(None);
+        assert!(matches!(err, SvenError::ProtocolMismatch(_)));
+        assert!(err.to_string().contains("an older protocol"), "{}", err);
+        assert!(mismatch_error(Some(99)).to_string().contains("protocol version 99"));
+    }
+}
@@ -0,0 +1,160 @@
+use crate::error::{Result, SvenError};
+use serde::de::{self, Deserializer, SeqAccess, Visitor};
+use serde::{Deserialize, Serialize, Serializer};
+use std::sync::atomic::{AtomicBool, Ordering};
+use zeroize::Zeroizing;
+
+// A decrypted value, kept as raw bytes since secrets such as keystores or
+// certificates needn't be text. Its buffer is zeroed when dropped and it never
+// shows up in Debug output, so it can't leak through logs or error messages.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct SecretString(Zeroizing<Vec<u8>>);
+
+static MLOCK_WARNED: AtomicBool = AtomicBool::new(false);
+
+impl SecretString {
+    // Takes over decrypted bytes without copying them
+    pub fn new(bytes: Zeroizing<Vec<u8>>) -> Self {
+        Self(bytes)
+    }
+
+    pub fn expose(&self) -> &[u8] {
+        &self.0
+    }
+
+    // The value as text, for outputs such as JSON or the clipboard that can't
+    // carry arbitrary bytes
+    pub fn as_str(&self) -> Result<&str> {
+        std::str::from_utf8(&self.0)
+            .map_err(|_| SvenError::ConfigError("value is not valid UTF-8".into()))
+    }
+
+    // Not a cryptographic hash; only store it where the value itself is
+    // already visible, such as the environment the value is exported to
+    pub fn digest(&self) -> u64 {
+        digest(&self.0)
+    }
+
+    // Keeps the value's pages out of swap. They stay locked for the life of
+    // the process since they may be shared with other locked values. Failure
+    // (usually RLIMIT_MEMLOCK) is reported once and otherwise ignored.
+    pub fn mlock(&self) {
+        if self.0.capacity() == 0 {
+            return;
+        }
+        // SAFETY: the range is the Vec's own allocation
+        let rc = unsafe { libc::mlock(self.0.as_ptr() as *const libc::c_void, self.0.capacity()) };
+        if rc != 0 && !MLOCK_WARNED.swap(true, Ordering::Relaxed) {
+            log::warn!(
+                "Failed to lock secrets in memory, they may be swapped to disk: {}",
+                std::io::Error::last_os_error()
+            );
+        }
+    }
+}
+
+// 64-bit FNV-1a, a cheap fingerprint for noticing changed values; it is
+// stable across builds, unlike std's DefaultHasher
+pub fn digest(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+// Appends to a secret buffer, growing it by hand so a reallocation doesn't
+// leave an unzeroed copy of the old contents behind
+pub fn extend_zeroized(buffer: &mut Zeroizing<Vec<u8>>, bytes: &[u8]) {
+    let needed = buffer.len() + bytes.len();
+    if needed > buffer.capacity() {
+        let mut grown = Zeroizing::new(Vec::with_capacity(needed.max(buffer.capacity() * 2).max(64)));
+        grown.extend_from_slice(buffer);
+        *buffer = grown;
+    }
+    buffer.extend_from_slice(bytes);
+}
+
+impl From<String> for SecretString {
+    fn from(value: String) -> Self {
+        Self(Zeroizing::new(value.into_bytes()))
+    }
+}
+
+// Serialized as bytes, which JSON writes as an array of numbers
+impl Serialize for SecretString {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for SecretString {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        deserializer.deserialize_bytes(SecretVisitor)
+    }
+}
+
+struct SecretVisitor;
+
+impl<'de> Visitor<'de> for SecretVisitor {
+    type Value = SecretString;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a byte array or string")
+    }
+
+    fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> std::result::Result<SecretString, E> {
+        Ok(SecretString(Zeroizing::new(bytes.to_vec())))
+    }
+
+    // Backups made before values were bytes hold them as strings
+    fn visit_str<E: de::Error>(self, value: &str) -> std::result::Result<SecretString, E> {
+        self.visit_bytes(value.as_bytes())
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> std::result::Result<SecretString, A::Error> {
+        let mut bytes = Zeroizing::new(Vec::with_capacity(seq.size_hint().unwrap_or(0)));
+        while let Some(byte) = seq.next_element()? {
+            extend_zeroized(&mut bytes, &[byte]);
+        }
+        Ok(SecretString(bytes))
+    }
+}
+
+impl std::fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SecretString([REDACTED])")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn non_utf8_values_round_trip() {
+        // Daemon messages and backups both carry values as JSON
+        let bytes = vec![0x00, 0xff, 0xfe, b'a', 0x80, b'\n', 0xc3];
+        let value = SecretString::new(Zeroizing::new(bytes.clone()));
+        let json = serde_json::to_vec(&value).unwrap();
+        let decoded: SecretString = serde_json::from_slice(&json).unwrap();
+        assert_eq!(decoded.expose(), bytes.as_slice());
+        assert!(decoded.as_str().is_err());
+    }
+
+    #[test]
+    fn extending_keeps_the_contents() {
+        let mut buffer = Zeroizing::new(Vec::new());
+        for chunk in [&b"ab"[..], &[0xff; 100], b"", b"cd"] {
+            extend_zeroized(&mut buffer, chunk);
+        }
+        let mut expected = b"ab".to_vec();
+        expected.extend([0xff; 100]);
+        expected.extend(b"cd");
+        assert_eq!(*buffer, expected);
+    }
+
+    #[test]
+    fn string_values_are_still_read() {
+        let decoded: SecretString = serde_json::from_str(r#""caf\u00e9 \"quoted\"""#).unwrap();
+        assert_eq!(decoded.as_str().unwrap(), "café \"quoted\"");
+    }
+}
@@ -0,0 +1,639 @@
+use crate::db;
+use crate::error::{Result, SvenError};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use std::path::{Path, PathBuf};
+
+// The rc file block `sven install-hook` manages; everything between the
+// markers is replaced on every install
+const BEGIN_MARKER: &str = "# >>> sven hook >>>";
+const END_MARKER: &str = "# <<< sven hook <<<";
+
+// Shells whose syntax `sven export` and `sven hook` print. Parsing rejects
+// any other name rather than guessing at a syntax.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportShell {
+    Bash,
+    Sh,
+    Zsh,
+    Fish,
+    /// csh and tcsh
+    #[value(alias = "tcsh")]
+    Csh,
+    /// Nushell
+    Nu,
+    /// PowerShell
+    #[value(alias = "powershell")]
+    Pwsh,
+    Elvish,
+    Xonsh,
+}
+
+impl ExportShell {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Bash => "bash",
+            Self::Sh => "sh",
+            Self::Zsh => "zsh",
+            Self::Fish => "fish",
+            Self::Csh => "csh",
+            Self::Nu => "nu",
+            Self::Pwsh => "pwsh",
+            Self::Elvish => "elvish",
+            Self::Xonsh => "xonsh",
+        }
+    }
+
+    // A statement that sets and exports `key`, or an error if the shell has
+    // no way to hold `value`
+    pub fn export(self, key: &str, value: &[u8]) -> Result<String> {
+        // Keys added before names were validated could inject code
+        db::validate_key(key)?;
+        let quoted = self.quote(value)?;
+        Ok(match self {
+            Self::Bash | Self::Sh | Self::Zsh => format!("export {}={}", key, quoted),
+            Self::Fish => format!("set -gx {} {}", key, quoted),
+            // Terminated so output joined onto one line by `eval "`...`"` still parses
+            Self::Csh => format!("setenv {} {};", key, quoted),
+            Self::Nu => format!("$env.{} = {}", key, quoted),
+            Self::Pwsh => format!("$env:{} = {}", key, quoted),
+            Self::Elvish => format!("set-env {} {}", key, quoted),
+            Self::Xonsh => format!("${} = {}", key, quoted),
+        })
+    }
+
+    // A statement that removes `key` from the environment, even if it isn't set
+    pub fn unset(self, key: &str) -> String {
+        match self {
+            Self::Bash | Self::Sh | Self::Zsh => format!("unset {}", key),
+            Self::Fish => format!("set -e {}", key),
+            Self::Csh => format!("unsetenv {};", key),
+            Self::Nu => format!("hide-env --ignore-errors {}", key),
+            Self::Pwsh => format!("Remove-Item -ErrorAction SilentlyContinue Env:{}", key),
+            Self::Elvish => format!("unset-env {}", key),
+            Self::Xonsh => format!("${{...}}.pop('{}', None)", key),
+        }
+    }
+
+    fn unrepresentable(self, what: &str) -> SvenError {
+        SvenError::ConfigError(format!("{} can't hold a value containing {}", self.name(), what))
+    }
+
+    // `value` as a string literal of the shell. Each is built on the shell's
+    // single quotes, which take everything literally but the quote itself;
+    // bytes that don't belong in them use the shell's byte escapes instead.
+    fn quote(self, value: &[u8]) -> Result<String> {
+        // The environment is made of C strings
+        if value.contains(&0) {
+            return Err(self.unrepresentable("a NUL byte"));
+        }
+        let text = std::str::from_utf8(value);
+        match self {
+            Self::Bash | Self::Zsh => match text {
+                Ok(text) if !text.chars().any(char::is_control) => Ok(posix_single_quote(text)),
+                // ANSI-C quoting, which also keeps the statement on one line
+                _ => {
+                    let mut quoted = String::from("$'");
+                    for piece in pieces(value) {
+                        match piece {
+                            Piece::Char('\\') => quoted.push_str("\\\\"),
+                            Piece::Char('\'') => quoted.push_str("\\'"),
+                            // Out of reach of history expansion in interactive shells
+                            Piece::Char('!') => quoted.push_str("\\x21"),
+                            Piece::Char(c) => quoted.push(c),
+                            Piece::Byte(b) => quoted.push_str(&format!("\\x{:02x}", b)),
+                        }
+                    }
+                    quoted.push('\'');
+                    Ok(quoted)
+                }
+            },
+            Self::Sh => {
+                // POSIX sh has no byte escapes inside quotes, so bytes that
+                // aren't UTF-8 come from printf, which takes octal escapes
+                let mut quoted = String::new();
+                let mut open = false;
+                for piece in pieces(value) {
+                    match piece {
+                        Piece::Byte(b) if b >= 0x80 => {
+                            if open {
+                                quoted.push('\'');
+                                open = false;
+                            }
+                            quoted.push_str(&format!("\"$(printf '\\{:03o}')\"", b));
+                        }
+                        piece => {
+                            if !open {
+                                quoted.push('\'');
+                                open = true;
+                            }
+                            match piece {
+                                Piece::Char('\'') => quoted.push_str("'\\''"),
+                                Piece::Char(c) => quoted.push(c),
+                                Piece::Byte(b) => quoted.push(char::from(b)),
+                            }
+                        }
+                    }
+                }
+                if open {
+                    quoted.push('\'');
+                } else if quoted.is_empty() {
+                    quoted.push_str("''");
+                }
+                Ok(quoted)
+            }
+            Self::Fish => {
+                // Only \ and ' are escaped inside single quotes; other bytes are
+                // written as \XHH between quoted runs
+                let mut quoted = String::new();
+                let mut open = false;
+                for piece in pieces(value) {
+                    match piece {
+                        Piece::Char(c) => {
+                            if !open {
+                                quoted.push('\'');
+                                open = true;
+                            }
+                            if c == '\\' || c == '\'' {
+                                quoted.push('\\');
+                            }
+                            quoted.push(c);
+                        }
+                        Piece::Byte(b) => {
+                            if open {
+                                quoted.push('\'');
+                                open = false;
+                            }
+                            quoted.push_str(&format!("\\X{:02x}", b));
+                        }
+                    }
+                }
+                if open {
+                    quoted.push('\'');
+                } else if quoted.is_empty() {
+                    quoted.push_str("''");
+                }
+                Ok(quoted)
+            }
+            Self::Csh => {
+                if value.iter().all(|b| (0x20..0x7f).contains(b)) {
+                    // History expansion reaches into single quotes, so ! is
+                    // escaped outside them like the quote itself
+                    let text = std::str::from_utf8(value).expect("printable ASCII is UTF-8");
+                    return Ok(format!("'{}'", text.replace('\'', "'\\''").replace('!', "'\\!'")));
+                }
+                // Command substitution always splits words at newlines
+                if value.contains(&b'\n') {
+                    return Err(self.unrepresentable("a newline"));
+                }
+                // Anything else is passed through base64 so csh never parses it
+                Ok(format!("\"`echo {} | base64 -d`\"", BASE64.encode(value)))
+            }
+            Self::Nu => {
+                let text = text.map_err(|_| self.unrepresentable("invalid UTF-8"))?;
+                // Raw strings end at a quote followed by as many #s as they
+                // started with, so use one more # than any such run in the value
+                let longest = text
+                    .match_indices('\'')
+                    .map(|(i, _)| text[i + 1..].chars().take_while(|&c| c == '#').count())
+                    .max()
+                    .unwrap_or(0);
+                let hashes = "#".repeat(longest + 1);
+                Ok(format!("r{}'{}'{}", hashes, text, hashes))
+            }
+            Self::Pwsh => {
+                let text = text.map_err(|_| self.unrepresentable("invalid UTF-8"))?;
+                // Single-quoted strings only treat quotes specially, including the
+                // typographic ones, and a doubled quote stands for itself
+                let mut quoted = String::with_capacity(text.len() + 2);
+                quoted.push('\'');
+                for c in text.chars() {
+                    if matches!(c, '\'' | '\u{2018}' | '\u{2019}' | '\u{201A}' | '\u{201B}') {
+                        quoted.push(c);
+                    }
+                    quoted.push(c);
+                }
+                quoted.push('\'');
+                Ok(quoted)
+            }
+            Self::Elvish => match text {
+                // Single-quoted strings are literal except for '' standing for '
+                Ok(text) => Ok(format!("'{}'", text.replace('\'', "''"))),
+                // Double quotes take \xHH for bytes that aren't UTF-8
+                Err(_) => {
+                    let mut quoted = String::from("\"");
+                    for piece in pieces(value) {
+                        match piece {
+                            Piece::Char(c) if c == '\\' || c == '"' => {
+                                quoted.push('\\');
+                                quoted.push(c);
+                            }
+                            Piece::Char(c) => quoted.push(c),
+                            Piece::Byte(b) => quoted.push_str(&format!("\\x{:02x}", b)),
+                        }
+                    }
+                    quoted.push('"');
+                    Ok(quoted)
+                }
+            },
+            Self::Xonsh => {
+                let text = text.map_err(|_| self.unrepresentable("invalid UTF-8"))?;
+                // A single-quoted Python string literal
+                let mut quoted = String::with_capacity(text.len() + 2);
+                quoted.push('\'');
+                for c in text.chars() {
+                    match c {
+                        '\\' => quoted.push_str("\\\\"),
+                        '\'' => quoted.push_str("\\'"),
+                        c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+                        c => quoted.push(c),
+                    }
+                }
+                quoted.push('\'');
+                Ok(quoted)
+            }
+        }
+    }
+}
+
+// 'text' with each ' closed, escaped and reopened
+fn posix_single_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+// A printable character, or one byte of a control character or of invalid UTF-8
+enum Piece {
+    Char(char),
+    Byte(u8),
+}
+
+fn pieces(value: &[u8]) -> Vec<Piece> {
+    let mut pieces = Vec::with_capacity(value.len());
+    for chunk in value.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            if c.is_control() {
+                let mut buf = [0u8; 4];
+                pieces.extend(c.encode_utf8(&mut buf).bytes().map(Piece::Byte));
+            } else {
+                pieces.push(Piece::Char(c));
+            }
+        }
+        pieces.extend(chunk.invalid().iter().copied().map(Piece::Byte));
+    }
+    pieces
+}
+
+// Shells with an integration script for `sven init` and `sven install-hook`
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InitShell {
+    Bash,
+    Zsh,
+    Fish,
+    /// csh and tcsh
+    Csh,
+    /// Nushell
+    Nu,
+    /// PowerShell
+    Pwsh,
+}
+
+impl InitShell {
+    // Guesses the user's shell from $SHELL
+    pub fn detect() -> Result<Self> {
+        let shell = std::env::var("SHELL").unwrap_or_default();
+        let name = Path::new(&shell).file_name().and_then(|name| name.to_str()).unwrap_or("");
+        match name {
+            "bash" => Ok(Self::Bash),
+            "zsh" => Ok(Self::Zsh),
+            "fish" => Ok(Self::Fish),
+            "csh" | "tcsh" => Ok(Self::Csh),
+            "nu" => Ok(Self::Nu),
+            "pwsh" => Ok(Self::Pwsh),
+            _ => Err(SvenError::ConfigError(format!(
+                "Could not detect a supported shell from SHELL={:?}; pass --shell",
+                shell
+            ))),
+        }
+    }
+
+    // The integration script, shipped inside the binary so it always
+    // matches the `sven hook` output it evaluates
+    pub fn script(self) -> &'static str {
+        match self {
+            Self::Bash => include_str!("../shell/load_secrets.bash"),
+            Self::Zsh => include_str!("../shell/load_secrets.zsh"),
+            Self::Fish => include_str!("../shell/load_secrets.fish"),
+            Self::Csh => include_str!("../shell/load_secrets.csh"),
+            Self::Nu => include_str!("../shell/load_secrets.nu"),
+            Self::Pwsh => include_str!("../shell/load_secrets.ps1"),
+        }
+    }
+
+    fn rc_file(self) -> Result<PathBuf> {
+        let home = dirs::home_dir()
+            .ok_or_else(|| SvenError::ConfigError("Could not find home directory".into()))?;
+        let config = dirs::config_dir()
+            .ok_or_else(|| SvenError::ConfigError("Could not find config directory".into()))?;
+        Ok(match self {
+            Self::Bash => home.join(".bashrc"),
+            Self::Zsh => std::env::var_os("ZDOTDIR")
+                .map(PathBuf::from)
+                .unwrap_or(home)
+                .join(".zshrc"),
+            Self::Fish => config.join("fish").join("config.fish"),
+            // tcsh reads ~/.tcshrc in preference to ~/.cshrc
+            Self::Csh if home.join(".tcshrc").exists() => home.join(".tcshrc"),
+            Self::Csh => home.join(".cshrc"),
+            Self::Nu => config.join("nushell").join("config.nu"),
+            Self::Pwsh => config.join("powershell").join("Microsoft.PowerShell_profile.ps1"),
+        })
+    }
+
+    // Nushell can only source files that exist when the config is parsed, so
+    // its script is written out by install-hook
+    fn nu_script_path() -> Result<PathBuf> {
+        dirs::data_local_dir()
+            .map(|dir| dir.join("sven").join("init.nu"))
+            .ok_or_else(|| SvenError::ConfigError("Could not find data directory".into()))
+    }
+
+    // What the rc file runs to load the integration
+    fn source_line(self) -> Result<String> {
+        Ok(match self {
+            Self::Bash => "eval \"$(sven init bash)\"".to_string(),
+            Self::Zsh => "eval \"$(sven init zsh)\"".to_string(),
+            Self::Fish => "sven init fish | source".to_string(),
+            Self::Csh => "eval \"`sven init csh`\"".to_string(),
+            Self::Nu => format!("source '{}'", Self::nu_script_path()?.display()),
+            Self::Pwsh => "sven init pwsh | Out-String | Invoke-Expression".to_string(),
+        })
+    }
+}
+
+fn write_nu_script(script_path: &Path) -> Result<()> {
+    if let Some(parent) = script_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(script_path, InitShell::Nu.script())?;
+    Ok(())
+}
+
+// Rewrites the Nushell script that install-hook wrote once it no longer
+// matches this binary, as after an upgrade, so the next shell loads the new
+// one. Called from the hook, which runs at every prompt.
+pub fn refresh_nu_script() -> Result<()> {
+    let script_path = InitShell::nu_script_path()?;
+    match std::fs::read_to_string(&script_path) {
+        Ok(script) if script != InitShell::Nu.script() => write_nu_script(&script_path),
+        _ => Ok(()),
+    }
+}
+
+pub enum InstallOutcome {
+    Installed,
+    Updated,
+    Unchanged,
+}
+
+// Adds the sven block to the shell's rc file, or replaces an existing one,
+// so running it again never duplicates the hook
+pub fn install_hook(shell: InitShell, rc_file: Option<PathBuf>) -> Result<(PathBuf, InstallOutcome)> {
+    let rc_file = match rc_file {
+        Some(rc_file) => rc_file,
+        None => shell.rc_file()?,
+    };
+    if shell == InitShell::Nu {
+        write_nu_script(&InitShell::nu_script_path()?)?;
+    }
+
+    let block = format!("{}\n{}\n{}\n", BEGIN_MARKER, shell.source_line()?, END_MARKER);
+    let contents = match std::fs::read_to_string(&rc_file) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e.into()),
+    };
+
+    let begin = contents.find(BEGIN_MARKER);
+    let end = begin.and_then(|begin| contents[begin..].find(END_MARKER).map(|end| begin + end));
+    let (updated, outcome) = match (begin, end) {
+        (Some(begin), Some(end)) => {
+            let mut end = end + END_MARKER.len();
+            if contents[end..].starts_with('\n') {
+                end += 1;
+            }
+            if contents[begin..end] == block {
+                return Ok((rc_file, InstallOutcome::Unchanged));
+            }
+            (format!("{}{}{}", &contents[..begin], block, &contents[end..]), InstallOutcome::Updated)
+        }
+        (Some(_), None) => {
+            return Err(SvenError::ConfigError(format!(
+                "{} has a '{}' line without a matching '{}'; fix it by hand",
+                rc_file.display(),
+                BEGIN_MARKER,
+                END_MARKER
+            )))
+        }
+        _ => {
+            let separator = if contents.is_empty() || contents.ends_with('\n') { "" } else { "\n" };
+            (format!("{}{}{}", contents, separator, block), InstallOutcome::Installed)
+        }
+    };
+
+    if let Some(parent) = rc_file.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    // Written in place rather than renamed over, so symlinked dotfiles keep working
+    std::fs::write(&rc_file, updated)?;
+    Ok((rc_file, outcome))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use std::process::Command;
+
+    const VAR: &str = "SVEN_QUOTING_TEST";
+
+    // The shells to check and how to run a script without reading any rc
+    // files. bash and sh are always there; the others are only checked by the
+    // ignored tests, run with `just test-shells`, which fail rather than skip
+    // when one of them is missing.
+    const SHELLS: &[(ExportShell, &str, &[&str])] = &[
+        (ExportShell::Bash, "bash", &["--norc", "--noprofile", "-c"]),
+        (ExportShell::Sh, "sh", &["-c"]),
+    ];
+
+    const OTHER_SHELLS: &[(ExportShell, &str, &[&str])] = &[
+        (ExportShell::Zsh, "zsh", &["-f", "-c"]),
+        (ExportShell::Fish, "fish", &["--no-config", "-c"]),
+        (ExportShell::Csh, "tcsh", &["-f", "-c"]),
+        (ExportShell::Nu, "nu", &["--no-config-file", "-c"]),
+        (ExportShell::Pwsh, "pwsh", &["-NoProfile", "-NonInteractive", "-Command"]),
+        (ExportShell::Elvish, "elvish", &["-norc", "-c"]),
+        (ExportShell::Xonsh, "xonsh", &["--no-rc", "-c"]),
+    ];
+
+    // Evaluates the export in a real shell and returns the value a child
+    // process finds in its environment
+    fn round_trip(shell: ExportShell, program: &str, args: &[&str], value: &[u8]) -> Vec<u8> {
+        let script = format!("{}\nprintenv {}", shell.export(VAR, value).unwrap(), VAR);
+        let output = Command::new(program)
+            .args(args)
+            .arg(&script)
+            .env_remove(VAR)
+            .output()
+            .unwrap_or_else(|e| panic!("can't run {}: {}", program, e));
+        assert!(
+            output.status.success(),
+            "{} failed on {:?}: {}",
+            program,
+            script,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        let mut stdout = output.stdout;
+        assert_eq!(stdout.pop(), Some(b'\n'));
+        stdout
+    }
+
+    // Arbitrary bytes, text, and runs of characters shells treat specially
+    fn values() -> impl Strategy<Value = Vec<u8>> {
+        let special = prop::sample::select(vec![
+            b'\'', b'"', b'\\', b'$', b'`', b'!', b'#', b'*', b'~', b'{', b'%', b';', b' ', b'\t', b'\n', b'a', 0x1b,
+            0x7f, 0x80, 0xff,
+        ]);
+        prop_oneof![
+            prop::collection::vec(1u8..=255, 0..48),
+            any::<String>().prop_map(|s| s.replace('\0', "").into_bytes()),
+            prop::collection::vec(special, 0..24),
+            prop::sample::select(TRICKY).prop_map(<[u8]>::to_vec),
+        ]
+    }
+
+    fn check_round_trips(
+        shells: &[(ExportShell, &str, &[&str])],
+        value: &[u8],
+    ) -> std::result::Result<(), TestCaseError> {
+        for &(shell, program, args) in shells {
+            // csh can't hold newlines, and some shells only hold text;
+            // export reports both as errors
+            let text_only = matches!(shell, ExportShell::Nu | ExportShell::Pwsh | ExportShell::Xonsh);
+            if (shell == ExportShell::Csh && value.contains(&b'\n'))
+                || (text_only && std::str::from_utf8(value).is_err())
+            {
+                prop_assert!(shell.export(VAR, value).is_err());
+                continue;
+            }
+            prop_assert_eq!(round_trip(shell, program, args, value), value, "{}", program);
+        }
+        Ok(())
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn values_round_trip_through_bash_and_sh(value in values()) {
+            check_round_trips(SHELLS, &value)?;
+        }
+
+        #[test]
+        #[ignore = "needs zsh, fish, tcsh, nu, pwsh, elvish and xonsh; run with `just test-shells`"]
+        fn values_round_trip_through_other_shells(value in values()) {
+            check_round_trips(OTHER_SHELLS, &value)?;
+        }
+    }
+
+    // Values that trip up quoting: quotes of every kind, a raw string's
+    // closing sequence, backslashes, a newline and an escape character
+    const TRICKY: &[&[u8]] = &[b"it's", b"\"#", b"'#", "\u{2019}".as_bytes(), b"C:\\", b"a\nb", b"\x1b[0m"];
+
+    fn quoted(shell: ExportShell) -> Vec<String> {
+        TRICKY.iter().map(|value| shell.quote(value).unwrap()).collect()
+    }
+
+    #[test]
+    fn nu_quoting() {
+        // One more # than follows any quote in the value
+        assert_eq!(
+            quoted(ExportShell::Nu),
+            ["r#'it's'#", "r#'\"#'#", "r##''#'##", "r#'\u{2019}'#", "r#'C:\\'#", "r#'a\nb'#", "r#'\x1b[0m'#"]
+        );
+        assert_eq!(ExportShell::Nu.quote(b"'#'##x'").unwrap(), "r###''#'##x''###");
+        assert_eq!(ExportShell::Nu.quote(b"").unwrap(), "r#''#");
+        assert!(ExportShell::Nu.quote(b"\xff").is_err());
+    }
+
+    #[test]
+    fn pwsh_quoting() {
+        assert_eq!(
+            quoted(ExportShell::Pwsh),
+            ["'it''s'", "'\"#'", "'''#'", "'\u{2019}\u{2019}'", "'C:\\'", "'a\nb'", "'\x1b[0m'"]
+        );
+        // Every quote PowerShell takes for ' is doubled; double quotes aren't
+        assert_eq!(
+            ExportShell::Pwsh.quote("\u{2018}\u{2019}\u{201A}\u{201B}\u{201C}".as_bytes()).unwrap(),
+            "'\u{2018}\u{2018}\u{2019}\u{2019}\u{201A}\u{201A}\u{201B}\u{201B}\u{201C}'"
+        );
+        assert!(ExportShell::Pwsh.quote(b"\xff").is_err());
+    }
+
+    #[test]
+    fn elvish_quoting() {
+        assert_eq!(
+            quoted(ExportShell::Elvish),
+            ["'it''s'", "'\"#'", "'''#'", "'\u{2019}'", "'C:\\'", "'a\nb'", "'\x1b[0m'"]
+        );
+        // Bytes that aren't UTF-8 need double quotes and their escapes
+        assert_eq!(ExportShell::Elvish.quote(b"\"a\\\xff\x1b").unwrap(), r#""\"a\\\xff\x1b""#);
+    }
+
+    #[test]
+    fn xonsh_quoting() {
+        assert_eq!(
+            quoted(ExportShell::Xonsh),
+            [r"'it\'s'", "'\"#'", r"'\'#'", "'\u{2019}'", r"'C:\\'", r"'a\u000ab'", r"'\u001b[0m'"]
+        );
+        assert!(ExportShell::Xonsh.quote(b"\xff").is_err());
+    }
+
+    #[test]
+    fn install_hook_edits_only_its_block() {
+        let dir = std::env::temp_dir().join(format!("sven-install-hook-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let rc_file = dir.join(".bashrc");
+        let install = || install_hook(InitShell::Bash, Some(rc_file.clone())).unwrap().1;
+        let read = || std::fs::read_to_string(&rc_file).unwrap();
+        let block = format!("{}\n{}\n{}\n", BEGIN_MARKER, InitShell::Bash.source_line().unwrap(), END_MARKER);
+
+        std::fs::write(&rc_file, "alias ll='ls -l'").unwrap();
+        assert!(matches!(install(), InstallOutcome::Installed));
+        assert_eq!(read(), format!("alias ll='ls -l'\n{}", block));
+
+        // Running it again leaves the file alone
+        assert!(matches!(install(), InstallOutcome::Unchanged));
+        assert_eq!(read(), format!("alias ll='ls -l'\n{}", block));
+
+        // An outdated block is replaced in place, keeping what surrounds it
+        let outdated = format!("{}\nsource ~/old/load_secrets.bash\n{}\n", BEGIN_MARKER, END_MARKER);
+        std::fs::write(&rc_file, format!("before\n{}after\n", outdated)).unwrap();
+        assert!(matches!(install(), InstallOutcome::Updated));
+        assert_eq!(read(), format!("before\n{}after\n", block));
+        assert!(matches!(install(), InstallOutcome::Unchanged));
+
+        // A block missing its end marker isn't guessed at
+        let broken = format!("{}\nsource ~/old/load_secrets.bash\n", BEGIN_MARKER);
+        std::fs::write(&rc_file, &broken).unwrap();
+        assert!(install_hook(InitShell::Bash, Some(rc_file.clone())).is_err());
+        assert_eq!(read(), broken);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn nul_bytes_are_rejected() {
+        for &(shell, _, _) in SHELLS.iter().chain(OTHER_SHELLS) {
+            assert!(shell.export(VAR, b"a\0b").is_err());
+        }
+    }
+}